@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use voxfile::{ReadOptions, VoxFile};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = VoxFile::from_bytes(data);
+    let lenient = ReadOptions { lenient: true, ..ReadOptions::default() };
+    let _ = VoxFile::from_bytes_with(data, &lenient);
+});
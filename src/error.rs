@@ -16,28 +16,211 @@
 
 use std::fmt::Formatter;
 
-pub type DotVoxResult<T, I> = std::result::Result<T, DotVoxError<I>>;
+/// Result of a .vox operation.
+pub type DotVoxResult<T> = std::result::Result<T, DotVoxError>;
 
-pub enum DotVoxError<I> {
+/// Errors reading, writing or interpreting .vox files.
+///
+/// Errors found while parsing carry the byte offset into the file they occurred at, and the path
+/// of the chunk being parsed, such as `MAIN/nTRN[3]` for the fourth nTRN chunk in MAIN.
+pub enum DotVoxError {
+    /// The input doesn't start with the `VOX ` magic number.
+    BadMagic,
+    /// The file has a version newer than this crate understands.
+    UnsupportedVersion(u32),
+    /// The file's top level chunk is not a MAIN chunk.
     NoMainChunk,
-    NomError(nom::error::Error<I>),
+    /// A chunk needs more bytes than are left in the file or its parent chunk.
+    TruncatedChunk { id: String, path: String, offset: usize, expected: usize, available: usize },
+    /// The content of a chunk doesn't match its format.
+    MalformedChunk { path: String, offset: usize, reason: String },
+    /// A chunk id or string is not valid UTF-8.
+    InvalidUtf8 { path: String, offset: usize },
+    /// An RGBA chunk doesn't hold 256 colors.
+    BadPaletteLength { path: String, offset: usize, length: usize },
     IOError(std::io::Error),
+    /// Two Scene Nodes share the same ID.
+    ///
+    /// Scene graph errors carry the path and offset of the offending node's chunk when the node
+    /// was read from a file.
+    DuplicateNodeId { id: u32, path: Option<String>, offset: Option<usize> },
+    /// A Scene Node refers to a child that doesn't exist.
+    DanglingNodeReference { node: u32, child: u32, path: Option<String>, offset: Option<usize> },
+    /// A Shape Node refers to a model that doesn't exist.
+    DanglingModelReference { node: u32, model: u32, path: Option<String>, offset: Option<usize> },
+    /// A Scene Node is the child of more than one node.
+    MultipleParents { id: u32, path: Option<String>, offset: Option<usize> },
+    /// A Scene Node is its own ancestor.
+    SceneGraphCycle { id: u32, path: Option<String>, offset: Option<usize> },
+    /// An attribute has a value that can't be interpreted.
+    InvalidAttribute { key: String, value: String },
+    /// A palette index map contains this index more than once.
+    InvalidIndexMap(u8),
+    /// The file exceeds one of the [`Limits`](crate::Limits) it was read with.
+    LimitExceeded { limit: Limit, path: String, offset: usize, value: usize, max: usize },
 }
 
-impl<I> std::fmt::Display for DotVoxError<I> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+/// One of the [`Limits`](crate::Limits) on files being read.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Limit {
+    /// [`Limits::max_file_size`](crate::Limits::max_file_size).
+    FileSize,
+    /// [`Limits::max_models`](crate::Limits::max_models).
+    Models,
+    /// [`Limits::max_voxels_per_model`](crate::Limits::max_voxels_per_model).
+    VoxelsPerModel,
+    /// [`Limits::max_dict_entries`](crate::Limits::max_dict_entries).
+    DictEntries,
+    /// [`Limits::max_string_length`](crate::Limits::max_string_length).
+    StringLength,
+    /// [`Limits::max_nesting_depth`](crate::Limits::max_nesting_depth).
+    NestingDepth,
+    /// [`Limits::max_group_children`](crate::Limits::max_group_children).
+    GroupChildren,
+    /// [`Limits::max_shape_models`](crate::Limits::max_shape_models).
+    ShapeModels,
+    /// [`Limits::max_transform_frames`](crate::Limits::max_transform_frames).
+    TransformFrames,
+}
+
+impl DotVoxError {
+    /// Byte offset into the file the error occurred at, for errors found while parsing.
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            DotVoxError::TruncatedChunk { offset, .. }
+            | DotVoxError::MalformedChunk { offset, .. }
+            | DotVoxError::InvalidUtf8 { offset, .. }
+            | DotVoxError::BadPaletteLength { offset, .. }
+            | DotVoxError::LimitExceeded { offset, .. } => Some(*offset),
+            DotVoxError::DuplicateNodeId { offset, .. }
+            | DotVoxError::DanglingNodeReference { offset, .. }
+            | DotVoxError::DanglingModelReference { offset, .. }
+            | DotVoxError::MultipleParents { offset, .. }
+            | DotVoxError::SceneGraphCycle { offset, .. } => *offset,
+            _ => None,
+        }
+    }
+
+    /// Path of the chunk the error occurred in, for errors found while parsing.
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            DotVoxError::TruncatedChunk { path, .. }
+            | DotVoxError::MalformedChunk { path, .. }
+            | DotVoxError::InvalidUtf8 { path, .. }
+            | DotVoxError::BadPaletteLength { path, .. }
+            | DotVoxError::LimitExceeded { path, .. } => Some(path),
+            DotVoxError::DuplicateNodeId { path, .. }
+            | DotVoxError::DanglingNodeReference { path, .. }
+            | DotVoxError::DanglingModelReference { path, .. }
+            | DotVoxError::MultipleParents { path, .. }
+            | DotVoxError::SceneGraphCycle { path, .. } => path.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+/// A problem recovered from while reading a file leniently.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReadWarning {
+    /// Path of the chunk the problem was found in, empty for the file header.
+    pub path: String,
+    /// Byte offset into the file the problem was found at, if known.
+    pub offset: Option<usize>,
+    /// Description of the problem.
+    pub message: String,
+}
+
+impl From<&DotVoxError> for ReadWarning {
+    fn from(err: &DotVoxError) -> Self {
+        ReadWarning {
+            path: err.path().unwrap_or_default().to_owned(),
+            offset: err.offset(),
+            message: err.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for ReadWarning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl From<std::io::Error> for DotVoxError {
+    fn from(err: std::io::Error) -> Self {
+        DotVoxError::IOError(err)
+    }
+}
+
+impl std::fmt::Display for DotVoxError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
+            DotVoxError::BadMagic => write!(f, "BadMagic"),
+            DotVoxError::UnsupportedVersion(version) => write!(f, "UnsupportedVersion({version})"),
             DotVoxError::NoMainChunk => write!(f, "NoMainChunk"),
-            DotVoxError::NomError(err) => write!(f, "NomError({})", err.code.description()),
-            DotVoxError::IOError(err) => write!(f, "{}", err),
+            DotVoxError::TruncatedChunk { id, path, offset, expected, available } => write!(
+                f,
+                "TruncatedChunk({path} at {offset}: {id:?} needs {expected} bytes, {available} available)"
+            ),
+            DotVoxError::MalformedChunk { path, offset, reason } => {
+                write!(f, "MalformedChunk({path} at {offset}: {reason})")
+            }
+            DotVoxError::InvalidUtf8 { path, offset } => write!(f, "InvalidUtf8({path} at {offset})"),
+            DotVoxError::BadPaletteLength { path, offset, length } => {
+                write!(f, "BadPaletteLength({path} at {offset}: {length} bytes)")
+            }
+            DotVoxError::IOError(err) => write!(f, "{err}"),
+            DotVoxError::DuplicateNodeId { id, path, offset } => {
+                write!(f, "DuplicateNodeId({}{id})", Location(path, offset))
+            }
+            DotVoxError::DanglingNodeReference { node, child, path, offset } => {
+                write!(f, "DanglingNodeReference({}{node} -> {child})", Location(path, offset))
+            }
+            DotVoxError::DanglingModelReference { node, model, path, offset } => {
+                write!(f, "DanglingModelReference({}{node} -> model {model})", Location(path, offset))
+            }
+            DotVoxError::MultipleParents { id, path, offset } => {
+                write!(f, "MultipleParents({}{id})", Location(path, offset))
+            }
+            DotVoxError::SceneGraphCycle { id, path, offset } => {
+                write!(f, "SceneGraphCycle({}{id})", Location(path, offset))
+            }
+            DotVoxError::InvalidAttribute { key, value } => {
+                write!(f, "InvalidAttribute({key} = {value:?})")
+            }
+            DotVoxError::InvalidIndexMap(index) => write!(f, "InvalidIndexMap({index})"),
+            DotVoxError::LimitExceeded { limit, path, offset, value, max } => {
+                write!(f, "LimitExceeded({path} at {offset}: {limit:?} of {value} exceeds {max})")
+            }
+        }
+    }
+}
+
+/// Formats the chunk path and offset of a scene graph error, if known, as a prefix.
+struct Location<'a>(&'a Option<String>, &'a Option<usize>);
+
+impl std::fmt::Display for Location<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Location(Some(path), Some(offset)) => write!(f, "{path} at {offset}: "),
+            Location(Some(path), None) => write!(f, "{path}: "),
+            Location(None, Some(offset)) => write!(f, "at {offset}: "),
+            Location(None, None) => Ok(()),
         }
     }
 }
 
-impl<I> std::fmt::Debug for DotVoxError<I> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self)
+impl std::fmt::Debug for DotVoxError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self}")
     }
 }
 
-impl<I> std::error::Error for DotVoxError<I> {}
\ No newline at end of file
+impl std::error::Error for DotVoxError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DotVoxError::IOError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
\ No newline at end of file
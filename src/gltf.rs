@@ -0,0 +1,194 @@
+//
+// Copyright 2021 Hans W. Uhlig. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use crate::material::{MaterialKind, MaterialProperties};
+use crate::types::{Color, VoxFile};
+
+/// glTF 2.0 metallic-roughness material parameters.
+///
+/// Colors are linear, as glTF expects. Parameters that need an extension are `None` when the
+/// material doesn't use them, see [`PbrMaterial::extensions_used`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct PbrMaterial {
+    /// `pbrMetallicRoughness.baseColorFactor`, the palette color with its alpha.
+    pub base_color: [f32; 4],
+    /// `pbrMetallicRoughness.metallicFactor`.
+    pub metallic: f32,
+    /// `pbrMetallicRoughness.roughnessFactor`.
+    pub roughness: f32,
+    /// `emissiveFactor`.
+    pub emissive: [f32; 3],
+    /// `KHR_materials_emissive_strength.emissiveStrength`.
+    pub emissive_strength: Option<f32>,
+    /// `KHR_materials_transmission.transmissionFactor`.
+    pub transmission: Option<f32>,
+    /// `KHR_materials_ior.ior`.
+    pub ior: Option<f32>,
+    /// `KHR_materials_specular.specularFactor`.
+    pub specular: Option<f32>,
+}
+
+impl PbrMaterial {
+    /// Map a palette color and its material to glTF parameters.
+    ///
+    /// * Diffuse materials are fully rough dielectrics.
+    /// * Metal materials use `_metal`, falling back to `_weight`, as the metallic factor.
+    /// * Glass materials use `_trans`, falling back to `_alpha`, as the transmission factor.
+    /// * Blend materials combine `_metal`, `_trans` and `_emit`.
+    /// * Emissive materials emit the palette color with a strength of `_emit * 10^_flux`.
+    ///
+    /// `_rough` and `_spec` are used as is, and `_ior` is stored as the IOR minus one.
+    pub fn from_material(color: &Color, properties: &MaterialProperties) -> PbrMaterial {
+        let rgb = [srgb_to_linear(color.r), srgb_to_linear(color.g), srgb_to_linear(color.b)];
+        let mut material = PbrMaterial {
+            base_color: [rgb[0], rgb[1], rgb[2], f32::from(color.a) / 255.0],
+            metallic: 0.0,
+            roughness: 1.0,
+            emissive: [0.0; 3],
+            emissive_strength: None,
+            transmission: None,
+            ior: None,
+            specular: None,
+        };
+        let weight = properties.weight.unwrap_or(1.0);
+        let (metallic, transmission, emission) = match properties.kind {
+            MaterialKind::Metal => (properties.metalness.or(Some(weight)), None, None),
+            MaterialKind::Glass => (None, properties.transparency.or(properties.alpha).or(Some(weight)), None),
+            MaterialKind::Emit => (None, None, properties.emission.or(Some(weight))),
+            MaterialKind::Blend => (properties.metalness, properties.transparency, properties.emission),
+            _ => return material,
+        };
+        material.metallic = metallic.unwrap_or(0.0).clamp(0.0, 1.0);
+        material.roughness = properties.roughness.unwrap_or(0.1).clamp(0.0, 1.0);
+        material.specular = properties.specular;
+        if let Some(transmission) = transmission {
+            material.transmission = Some(transmission.clamp(0.0, 1.0));
+            material.ior = Some(1.0 + properties.ior.unwrap_or(0.3));
+        }
+        if let Some(emission) = emission {
+            let strength = emission * 10f32.powf(properties.flux.unwrap_or(0.0));
+            if strength > 0.0 {
+                material.emissive = rgb;
+                if (strength - 1.0).abs() > f32::EPSILON {
+                    material.emissive_strength = Some(strength);
+                }
+            }
+        }
+        material
+    }
+
+    /// Names of the glTF extensions these parameters need.
+    pub fn extensions_used(&self) -> Vec<&'static str> {
+        let mut extensions = Vec::new();
+        if self.emissive_strength.is_some() {
+            extensions.push("KHR_materials_emissive_strength");
+        }
+        if self.transmission.is_some() {
+            extensions.push("KHR_materials_transmission");
+        }
+        if self.ior.is_some() {
+            extensions.push("KHR_materials_ior");
+        }
+        if self.specular.is_some() {
+            extensions.push("KHR_materials_specular");
+        }
+        extensions
+    }
+}
+
+impl VoxFile {
+    /// glTF material parameters for palette index `index`.
+    pub fn pbr_material(&self, index: u8) -> PbrMaterial {
+        let material = self.material_for_index(index);
+        PbrMaterial::from_material(&self.palette[usize::from(index)], &material.material_properties())
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = f32::from(value) / 255.0;
+    if value <= 0.040_45 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::float_cmp)]
+mod tests {
+    use super::PbrMaterial;
+    use crate::{Color, MaterialKind, MaterialProperties, VoxFile};
+
+    fn color(r: u8, g: u8, b: u8, a: u8) -> Color {
+        Color { name: None, r, g, b, a }
+    }
+
+    #[test]
+    fn test_diffuse() {
+        let material = PbrMaterial::from_material(&color(255, 0, 0, 255), &MaterialProperties::default());
+        assert_eq!(material.base_color, [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(material.metallic, 0.0);
+        assert_eq!(material.roughness, 1.0);
+        assert_eq!(material.emissive, [0.0; 3]);
+        assert!(material.extensions_used().is_empty());
+    }
+
+    #[test]
+    fn test_metal_glass_emit() {
+        let metal = MaterialProperties {
+            kind: MaterialKind::Metal,
+            metalness: Some(0.8),
+            roughness: Some(0.25),
+            specular: Some(0.5),
+            ..MaterialProperties::default()
+        };
+        let material = PbrMaterial::from_material(&color(128, 128, 128, 255), &metal);
+        assert_eq!(material.metallic, 0.8);
+        assert_eq!(material.roughness, 0.25);
+        assert_eq!(material.extensions_used(), vec!["KHR_materials_specular"]);
+
+        let glass = MaterialProperties {
+            kind: MaterialKind::Glass,
+            transparency: Some(0.5),
+            ior: Some(0.5),
+            ..MaterialProperties::default()
+        };
+        let material = PbrMaterial::from_material(&color(255, 255, 255, 255), &glass);
+        assert_eq!(material.transmission, Some(0.5));
+        assert_eq!(material.ior, Some(1.5));
+        assert_eq!(material.extensions_used(), vec!["KHR_materials_transmission", "KHR_materials_ior"]);
+
+        let emit = MaterialProperties {
+            kind: MaterialKind::Emit,
+            emission: Some(0.5),
+            flux: Some(1.0),
+            ..MaterialProperties::default()
+        };
+        let material = PbrMaterial::from_material(&color(0, 255, 0, 255), &emit);
+        assert_eq!(material.emissive, [0.0, 1.0, 0.0]);
+        assert_eq!(material.emissive_strength, Some(5.0));
+    }
+
+    #[test]
+    fn test_sample_file() {
+        let file = VoxFile::open("vox/streetlamp.vox").expect("Error loading test file.");
+        for index in 0..=255 {
+            let material = file.pbr_material(index);
+            let kind = file.material_for_index(index).material_properties().kind;
+            assert_eq!(material.emissive != [0.0; 3], kind == MaterialKind::Emit);
+        }
+    }
+}
@@ -14,28 +14,55 @@
 // limitations under the License.
 //
 
-//! Based on the MagicaVoxel .vox File Format.
+//! Based on the `MagicaVoxel` .vox File Format.
 //!
 //! Spec:
-//! https://github.com/ephtracy/voxel-model/blob/master/MagicaVoxel-file-format-vox.txt
-//! https://github.com/ephtracy/voxel-model/blob/master/MagicaVoxel-file-format-vox-extension.txt
+//! <https://github.com/ephtracy/voxel-model/blob/master/MagicaVoxel-file-format-vox.txt>
+//! <https://github.com/ephtracy/voxel-model/blob/master/MagicaVoxel-file-format-vox-extension.txt>
+//!
+//! ```no_run
+//! use voxfile::VoxFile;
+//!
+//! let file = VoxFile::open("vox/3x3x3.vox").expect("Unable to load file");
+//! println!("Loaded {} models", file.models.len());
+//! ```
 
 #![forbid(unsafe_code)]
 #![warn(clippy::pedantic)]
+// Chunk variants are named after the four character chunk ids in the file.
+#![allow(clippy::upper_case_acronyms)]
+#![allow(clippy::must_use_candidate)]
 // TODO: Remove These
 #![allow(unused_imports)]
 #![allow(unused_variables)]
 #![allow(dead_code)]
 
 mod error;
+mod gltf;
+mod material;
+mod merge;
+mod palette;
 mod parser;
+mod quantize;
+mod scene;
 mod types;
+mod volume;
 mod writer;
 
 pub use self::error::DotVoxResult;
 pub use self::error::DotVoxError;
+pub use self::error::Limit;
+pub use self::error::ReadWarning;
+pub use self::gltf::PbrMaterial;
+pub use self::material::{MaterialKind, MaterialProperties};
+pub use self::palette::{PaletteGroup, PaletteSortKey, PALETTE_ROWS, PALETTE_ROW_LENGTH};
 //pub use self::parser::DotVoxParser;
+pub use self::parser::{read_chunks, read_chunks_with, Limits, ReadOptions};
+pub use self::quantize::{Quantized, Quantizer, TrueColorVoxel};
+pub use self::scene::{BreadthFirst, DepthFirst, Matrix, SceneGraph, ShapeInstance};
 pub use self::types::*;
+pub use self::volume::{Volume, VolumeVoxel};
+pub use self::writer::write_chunks;
 
 
 #[cfg(test)]
@@ -0,0 +1,555 @@
+//
+// Copyright 2021 Hans W. Uhlig. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use std::convert::TryFrom;
+use crate::types::{Color, Dict, Material, MaterialV1, MaterialV2, VoxFile};
+
+/// Kind of a MATL material (`_type`).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum MaterialKind {
+    /// `_diffuse`
+    #[default]
+    Diffuse,
+    /// `_metal`
+    Metal,
+    /// `_glass`
+    Glass,
+    /// `_emit`
+    Emit,
+    /// `_blend`
+    Blend,
+    /// `_media`
+    Media,
+    /// `_cloud`
+    Cloud,
+    /// Any other `_type` value, kept as written.
+    Other(String),
+}
+
+impl MaterialKind {
+    /// Parse a `_type` value.
+    pub fn parse(value: &str) -> MaterialKind {
+        match value {
+            "_diffuse" => MaterialKind::Diffuse,
+            "_metal" => MaterialKind::Metal,
+            "_glass" => MaterialKind::Glass,
+            "_emit" => MaterialKind::Emit,
+            "_blend" => MaterialKind::Blend,
+            "_media" => MaterialKind::Media,
+            "_cloud" => MaterialKind::Cloud,
+            other => MaterialKind::Other(other.to_owned()),
+        }
+    }
+
+    /// The `_type` value of this kind.
+    pub fn as_str(&self) -> &str {
+        match self {
+            MaterialKind::Diffuse => "_diffuse",
+            MaterialKind::Metal => "_metal",
+            MaterialKind::Glass => "_glass",
+            MaterialKind::Emit => "_emit",
+            MaterialKind::Blend => "_blend",
+            MaterialKind::Media => "_media",
+            MaterialKind::Cloud => "_cloud",
+            MaterialKind::Other(other) => other,
+        }
+    }
+}
+
+/// Typed view of the properties of a MATL material.
+///
+/// Numeric properties are `None` when absent from the file. Properties this crate doesn't know
+/// about, and known properties whose value isn't a number, are kept in `extra` so nothing is lost
+/// when the properties are written back.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MaterialProperties {
+    /// Kind of material (`_type`).
+    pub kind: MaterialKind,
+    /// Blend weight (`_weight`), (0.0 - 1.0].
+    pub weight: Option<f32>,
+    /// Roughness (`_rough`).
+    pub roughness: Option<f32>,
+    /// Specular reflection (`_spec`).
+    pub specular: Option<f32>,
+    /// Index of refraction (`_ior`).
+    pub ior: Option<f32>,
+    /// Light attenuation (`_att`).
+    pub attenuation: Option<f32>,
+    /// Light flux (`_flux`).
+    pub flux: Option<f32>,
+    /// Emission (`_emit`).
+    pub emission: Option<f32>,
+    /// Low dynamic range emission (`_ldr`).
+    pub ldr: Option<f32>,
+    /// Metalness (`_metal`).
+    pub metalness: Option<f32>,
+    /// Transparency (`_trans`).
+    pub transparency: Option<f32>,
+    /// Alpha (`_alpha`).
+    pub alpha: Option<f32>,
+    /// Density (`_d`).
+    pub density: Option<f32>,
+    /// Specular (`_sp`).
+    pub sp: Option<f32>,
+    /// Scattering phase (`_g`).
+    pub phase: Option<f32>,
+    /// Media type (`_media`).
+    pub media: Option<f32>,
+    /// Plastic (`_plastic`).
+    pub plastic: Option<f32>,
+    /// Every other property, in file order.
+    pub extra: Dict,
+}
+
+impl MaterialProperties {
+    /// Parse the properties of a MATL material.
+    pub fn from_dict(dict: &Dict) -> MaterialProperties {
+        let mut properties = MaterialProperties::default();
+        for (key, value) in dict.iter() {
+            if key == "_type" {
+                properties.kind = MaterialKind::parse(value);
+                continue;
+            }
+            let mut parsed = false;
+            if let Ok(number) = value.trim().parse::<f32>() {
+                for (name, field) in properties.numbers_mut() {
+                    if name == key {
+                        *field = Some(number);
+                        parsed = true;
+                    }
+                }
+            }
+            if !parsed {
+                properties.extra.insert(key, value);
+            }
+        }
+        properties
+    }
+
+    /// Build the DICT for these properties.
+    pub fn to_dict(&self) -> Dict {
+        let mut dict = Dict::new();
+        self.write_dict(&mut dict);
+        dict
+    }
+
+    /// Store these properties in `dict`.
+    ///
+    /// Values already in `dict` that are numerically unchanged keep their original text, and
+    /// properties set to `None` are removed, so writing back unmodified properties leaves `dict`
+    /// untouched.
+    pub fn write_dict(&self, dict: &mut Dict) {
+        if dict.contains_key("_type") || self.kind != MaterialKind::Diffuse {
+            dict.insert("_type", self.kind.as_str());
+        }
+        for (key, number) in self.numbers() {
+            match number {
+                Some(number) => {
+                    if dict.get(key).and_then(|value| value.trim().parse::<f32>().ok()) != Some(number) {
+                        dict.insert(key, number.to_string());
+                    }
+                }
+                None => {
+                    if !self.extra.contains_key(key) {
+                        dict.remove(key);
+                    }
+                }
+            }
+        }
+        let stale: Vec<String> = dict
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|key| *key != "_type" && !self.numbers().iter().any(|(name, _)| name == key))
+            .filter(|key| !self.extra.contains_key(key))
+            .map(str::to_owned)
+            .collect();
+        for key in stale {
+            dict.remove(&key);
+        }
+        for (key, value) in self.extra.iter() {
+            dict.insert(key, value);
+        }
+    }
+
+    fn numbers(&self) -> [(&'static str, Option<f32>); 16] {
+        [
+            ("_weight", self.weight),
+            ("_rough", self.roughness),
+            ("_spec", self.specular),
+            ("_ior", self.ior),
+            ("_att", self.attenuation),
+            ("_flux", self.flux),
+            ("_emit", self.emission),
+            ("_ldr", self.ldr),
+            ("_metal", self.metalness),
+            ("_trans", self.transparency),
+            ("_alpha", self.alpha),
+            ("_d", self.density),
+            ("_sp", self.sp),
+            ("_g", self.phase),
+            ("_media", self.media),
+            ("_plastic", self.plastic),
+        ]
+    }
+
+    fn numbers_mut(&mut self) -> [(&'static str, &mut Option<f32>); 16] {
+        [
+            ("_weight", &mut self.weight),
+            ("_rough", &mut self.roughness),
+            ("_spec", &mut self.specular),
+            ("_ior", &mut self.ior),
+            ("_att", &mut self.attenuation),
+            ("_flux", &mut self.flux),
+            ("_emit", &mut self.emission),
+            ("_ldr", &mut self.ldr),
+            ("_metal", &mut self.metalness),
+            ("_trans", &mut self.transparency),
+            ("_alpha", &mut self.alpha),
+            ("_d", &mut self.density),
+            ("_sp", &mut self.sp),
+            ("_g", &mut self.phase),
+            ("_media", &mut self.media),
+            ("_plastic", &mut self.plastic),
+        ]
+    }
+}
+
+impl MaterialV2 {
+    /// Typed view of this material's properties.
+    pub fn material_properties(&self) -> MaterialProperties {
+        MaterialProperties::from_dict(&self.properties)
+    }
+
+    /// Replace this material's properties, keeping the original text of unchanged values.
+    pub fn set_material_properties(&mut self, properties: &MaterialProperties) {
+        properties.write_dict(&mut self.properties);
+    }
+}
+
+impl MaterialV1 {
+    /// Typed properties equivalent to this legacy material.
+    ///
+    /// The normalized MATT values are mapped into the ranges MATL uses: plastic, roughness,
+    /// specular and attenuation keep their 0 to 1 range as `_plastic`, `_rough`, `_spec` and
+    /// `_att`, and glow becomes `_ldr`. IOR spans 1 to 3 and is stored less 1 as `_ior`, and power
+    /// spans the 0 to 4 of `_flux`. The weight is kept as `_weight` and is also the `_metal`,
+    /// `_trans` or `_emit` of metal, glass and emissive materials. MATL has no equivalent of the
+    /// `isTotalPower` flag, so it is dropped.
+    pub fn material_properties(&self) -> MaterialProperties {
+        let kind = match self.kind {
+            0 => MaterialKind::Diffuse,
+            1 => MaterialKind::Metal,
+            2 => MaterialKind::Glass,
+            3 => MaterialKind::Emit,
+            other => MaterialKind::Other(other.to_string()),
+        };
+        let weight_for = |of: &MaterialKind| if kind == *of { Some(self.weight) } else { None };
+        MaterialProperties {
+            weight: Some(self.weight),
+            plastic: self.plastic,
+            roughness: self.roughness,
+            specular: self.specular,
+            ior: self.ior.map(|ior| ior * 2.0),
+            attenuation: self.attenuation,
+            flux: self.power.map(|power| power * 4.0),
+            ldr: self.glow,
+            metalness: weight_for(&MaterialKind::Metal),
+            transparency: weight_for(&MaterialKind::Glass),
+            emission: weight_for(&MaterialKind::Emit),
+            kind,
+            ..MaterialProperties::default()
+        }
+    }
+
+    /// Convert this legacy MATT material into an equivalent MATL material with the same id.
+    pub fn to_v2(&self) -> MaterialV2 {
+        let mut properties = Dict::new();
+        let material = self.material_properties();
+        properties.insert("_type", material.kind.as_str());
+        material.write_dict(&mut properties);
+        MaterialV2 { id: self.id, properties }
+    }
+}
+
+impl From<&MaterialV1> for MaterialV2 {
+    fn from(material: &MaterialV1) -> MaterialV2 {
+        material.to_v2()
+    }
+}
+
+impl MaterialV2 {
+    /// The default diffuse material `MagicaVoxel` uses for palette index `id`.
+    pub fn diffuse(id: u32) -> MaterialV2 {
+        let mut properties = Dict::new();
+        properties.insert("_type", "_diffuse");
+        properties.insert("_weight", "1");
+        properties.insert("_rough", "0.1");
+        properties.insert("_spec", "0.5");
+        properties.insert("_ior", "0.3");
+        MaterialV2 { id, properties }
+    }
+}
+
+impl VoxFile {
+    /// The effective material of palette index `index`.
+    ///
+    /// This is the MATL material with that id, otherwise the MATT material with that id converted
+    /// to MATL, otherwise [`MaterialV2::diffuse`]. Materials of indices 1 to 255 have the same id
+    /// as the index, and the material of index 0 has id 256.
+    pub fn material_for_index(&self, index: u8) -> MaterialV2 {
+        let id = material_id_for_index(index);
+        let mut legacy = None;
+        for material in &self.materials {
+            match material {
+                Material::V2(material) if material.id == id => return material.clone(),
+                Material::V1(material) if material.id == id && legacy.is_none() => legacy = Some(material),
+                _ => {}
+            }
+        }
+        legacy.map_or_else(|| MaterialV2::diffuse(id), MaterialV1::to_v2)
+    }
+
+    /// Set the material of palette index `index`.
+    ///
+    /// An existing MATL material for the index is updated in place, keeping the text of unchanged
+    /// values, and any MATT material for it is replaced. Otherwise a new MATL material is inserted
+    /// in id order. The palette color is left unchanged, see [`VoxFile::set_palette_entry`] to set
+    /// both.
+    pub fn set_material_for_index(&mut self, index: u8, properties: &MaterialProperties) {
+        let id = material_id_for_index(index);
+        let existing = self
+            .materials
+            .iter()
+            .position(|material| matches!(material, Material::V2(material) if material.id == id))
+            .or_else(|| self.materials.iter().position(|material| material_id(material) == id));
+        if let Some(position) = existing {
+            if let Material::V2(material) = &mut self.materials[position] {
+                material.set_material_properties(properties);
+            } else {
+                self.materials[position] = Material::V2(MaterialV2 { id, properties: properties.to_dict() });
+            }
+            let mut current = 0;
+            self.materials.retain(|material| {
+                let keep = current == position || material_id(material) != id;
+                current += 1;
+                keep
+            });
+        } else {
+            let position = self
+                .materials
+                .iter()
+                .position(|material| material_id(material) > id)
+                .unwrap_or(self.materials.len());
+            self.materials
+                .insert(position, Material::V2(MaterialV2 { id, properties: properties.to_dict() }));
+        }
+    }
+
+    /// Set the color and material of palette index `index` together.
+    ///
+    /// See [`VoxFile::set_material_for_index`] for how the material is stored.
+    pub fn set_palette_entry(&mut self, index: u8, color: Color, properties: &MaterialProperties) {
+        self.palette[usize::from(index)] = color;
+        self.set_material_for_index(index, properties);
+    }
+
+    /// The effective material of every palette index, indexed by palette index.
+    pub fn material_table(&self) -> Vec<MaterialV2> {
+        (0..=255).map(|index| self.material_for_index(index)).collect()
+    }
+
+    /// Replace every legacy MATT material with its MATL equivalent.
+    ///
+    /// See [`MaterialV1::to_v2`] for how the properties are mapped.
+    pub fn upgrade_materials(&mut self) {
+        for material in &mut self.materials {
+            if let Material::V1(legacy) = material {
+                *material = Material::V2(legacy.to_v2());
+            }
+        }
+    }
+}
+
+/// The id of the material of palette index `index`, which is 256 for index 0.
+pub(crate) fn material_id_for_index(index: u8) -> u32 {
+    if index == 0 { 256 } else { u32::from(index) }
+}
+
+/// The palette index of the material with id `id`, if it is one of 1 to 256.
+pub(crate) fn index_for_material_id(id: u32) -> Option<u8> {
+    match id {
+        256 => Some(0),
+        id => u8::try_from(id).ok().filter(|index| *index != 0),
+    }
+}
+
+fn material_id(material: &Material) -> u32 {
+    match material {
+        Material::V1(material) => material.id,
+        Material::V2(material) => material.id,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MaterialKind, MaterialProperties};
+    use std::convert::TryFrom;
+    use crate::{Color, Dict, Material, MaterialV1, ReadOptions, VoxFile};
+
+    #[test]
+    fn test_parse_properties() {
+        let mut dict = Dict::new();
+        dict.insert("_type", "_metal");
+        dict.insert("_weight", "1.0");
+        dict.insert("_rough", "0.1");
+        dict.insert("_metal", "0.75");
+        dict.insert("_media", "_scatter");
+        dict.insert("_spec_p", "0.5");
+        let properties = MaterialProperties::from_dict(&dict);
+        assert_eq!(properties.kind, MaterialKind::Metal);
+        assert_eq!(properties.weight, Some(1.0));
+        assert_eq!(properties.roughness, Some(0.1));
+        assert_eq!(properties.metalness, Some(0.75));
+        assert_eq!(properties.media, None);
+        assert_eq!(properties.extra.get("_media"), Some("_scatter"));
+        assert_eq!(properties.extra.get("_spec_p"), Some("0.5"));
+        let mut rewritten = dict.clone();
+        properties.write_dict(&mut rewritten);
+        assert_eq!(rewritten, dict);
+        assert_eq!(properties.to_dict().get("_weight"), Some("1"));
+
+        let mut changed = properties;
+        changed.kind = MaterialKind::Other(String::from("_custom"));
+        changed.roughness = None;
+        changed.emission = Some(0.25);
+        changed.extra.remove("_spec_p");
+        changed.write_dict(&mut dict);
+        let expected: Vec<(&str, &str)> = vec![
+            ("_type", "_custom"),
+            ("_weight", "1.0"),
+            ("_metal", "0.75"),
+            ("_media", "_scatter"),
+            ("_emit", "0.25"),
+        ];
+        assert_eq!(dict.iter().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_sample_materials() {
+        let mut file = VoxFile::open("vox/streetlamp.vox").expect("Error loading test file.");
+        for material in &mut file.materials {
+            if let Material::V2(material) = material {
+                let original = material.properties.clone();
+                let properties = material.material_properties();
+                assert_eq!(properties.kind, MaterialKind::parse(original.get("_type").unwrap_or("_diffuse")));
+                material.set_material_properties(&properties);
+                assert_eq!(material.properties, original);
+            }
+        }
+    }
+
+    #[test]
+    fn test_convert_v1() {
+        let legacy = MaterialV1 {
+            id: 7,
+            kind: 3,
+            weight: 0.5,
+            plastic: None,
+            roughness: Some(0.1),
+            specular: None,
+            ior: Some(0.15),
+            attenuation: None,
+            power: Some(0.5),
+            glow: Some(0.25),
+            is_total_power: true,
+        };
+        let material = legacy.to_v2();
+        assert_eq!(material.id, 7);
+        let expected: Vec<(&str, &str)> = vec![
+            ("_type", "_emit"),
+            ("_weight", "0.5"),
+            ("_rough", "0.1"),
+            ("_ior", "0.3"),
+            ("_flux", "2"),
+            ("_emit", "0.5"),
+            ("_ldr", "0.25"),
+        ];
+        assert_eq!(material.properties.iter().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_upgrade_on_load() {
+        let file = VoxFile::open("vox/T-Rex.vox").expect("Error loading test file.");
+        assert!(file.materials.iter().all(|material| matches!(material, Material::V1(_))));
+        let options = ReadOptions { upgrade_materials: true, ..ReadOptions::default() };
+        let upgraded = VoxFile::open_with("vox/T-Rex.vox", &options).expect("Error loading test file.");
+        assert_eq!(upgraded.materials.len(), file.materials.len());
+        for (legacy, material) in file.materials.iter().zip(&upgraded.materials) {
+            match (legacy, material) {
+                (Material::V1(legacy), Material::V2(material)) => {
+                    assert_eq!(material.id, legacy.id);
+                    let properties = material.material_properties();
+                    assert_eq!(properties.kind, MaterialKind::Diffuse);
+                    assert_eq!(properties.roughness, legacy.roughness);
+                    assert_eq!(properties.specular, legacy.specular);
+                    assert_eq!(properties.ior, legacy.ior.map(|ior| ior * 2.0));
+                }
+                _ => panic!("Material was not upgraded"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_material_for_index() {
+        let mut file = VoxFile::open("vox/T-Rex.vox").expect("Error loading test file.");
+        let legacy = match &file.materials[0] {
+            Material::V1(material) => material.clone(),
+            Material::V2(_) => panic!("Expected a MATT material"),
+        };
+        let index = u8::try_from(legacy.id).expect("MATT id out of range");
+        assert_eq!(file.material_for_index(index), legacy.to_v2());
+
+        let properties = MaterialProperties {
+            kind: MaterialKind::Metal,
+            metalness: Some(0.5),
+            ..MaterialProperties::default()
+        };
+        file.set_material_for_index(index, &properties);
+        assert_eq!(file.material_for_index(index).material_properties(), properties);
+        assert_eq!(file.materials.iter().filter(|material| match material {
+            Material::V1(material) => material.id == u32::from(index),
+            Material::V2(material) => material.id == u32::from(index),
+        }).count(), 1);
+
+        let mut file = VoxFile::default();
+        assert_eq!(file.material_for_index(3), crate::MaterialV2::diffuse(3));
+        file.set_material_for_index(9, &properties);
+        file.set_material_for_index(2, &properties);
+        let table = file.material_table();
+        assert_eq!(table.len(), 256);
+        assert_eq!(table[9].material_properties(), properties);
+        assert_eq!(table[2].material_properties(), properties);
+        assert_eq!(table[5], crate::MaterialV2::diffuse(5));
+        assert!(matches!(&file.materials[0], Material::V2(material) if material.id == 2));
+
+        file.set_palette_entry(0, Color::from_abgr(0xff00_00ff), &properties);
+        assert_eq!(file.palette[0], Color::from_abgr(0xff00_00ff));
+        assert_eq!(file.material_for_index(0).id, 256);
+        assert_eq!(file.material_for_index(0).material_properties(), properties);
+        assert_eq!(file.material_for_index(1), crate::MaterialV2::diffuse(1));
+        assert!(matches!(file.materials.last(), Some(Material::V2(material)) if material.id == 256));
+    }
+}
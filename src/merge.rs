@@ -0,0 +1,321 @@
+//
+// Copyright 2021 Hans W. Uhlig. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use std::collections::{HashMap, HashSet};
+use crate::scene::child_ids;
+use crate::types::{
+    Color, Dict, GroupNode, Material, MaterialV2, SceneNode, ShapeNode, TransformNode, VoxFile,
+};
+
+/// A palette entry of one of the merged files: its color and material properties.
+type Entry = (u32, Dict);
+
+impl VoxFile {
+    /// Combine two files into one.
+    ///
+    /// The models of `b` follow those of `a`, and the scene graphs of both are placed side by side
+    /// under a new root group. The nodes, models and layers of both files are renumbered from 0 in
+    /// order, so those of `b` follow those of `a`, and references to ones that don't exist become
+    /// `u32::MAX`. Files without a scene graph get one Transform and Shape Node per model.
+    ///
+    /// The colors used by either file, together with their materials, form the new palette. If
+    /// there are more than 255 of them the most used are kept and voxels of the others use the
+    /// nearest kept color. Cameras and render settings are taken from `a`. Palette notes, the
+    /// index map and unknown chunks are dropped, as they don't apply to the new palette.
+    // Model and node counts are read from u32 fields, so they fit in one.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn merge(a: &VoxFile, b: &VoxFile) -> VoxFile {
+        let mut merged = VoxFile {
+            version: a.version.max(b.version),
+            cameras: a.cameras.clone(),
+            render_objects: a.render_objects.clone(),
+            pack: a.pack || b.pack,
+            ..VoxFile::default()
+        };
+
+        let (entries, tables) = unify_palettes(&[a, b]);
+        for ((color, properties), index) in entries.iter().zip(1..=255u8) {
+            merged.palette[usize::from(index)] = Color::from_abgr(*color);
+            if *properties != MaterialV2::diffuse(0).properties {
+                let id = u32::from(index);
+                merged.materials.push(Material::V2(MaterialV2 { id, properties: properties.clone() }));
+            }
+        }
+
+        let mut root = GroupNode { id: 1, attrib: Dict::new(), children: Vec::new() };
+        let mut scenegraph = Vec::new();
+        let mut next_node = 2;
+        for (file, table) in [a, b].iter().zip(&tables) {
+            let mut models = HashMap::new();
+            for (position, model) in file.models.iter().enumerate() {
+                let mut model = model.clone();
+                model.id = merged.models.len() as u32;
+                models.insert(position as u32, model.id);
+                for voxel in &mut model.voxels {
+                    voxel.i = table[usize::from(voxel.i)];
+                }
+                merged.models.push(model);
+            }
+            let mut layers = HashMap::new();
+            for file_layer in &file.layers {
+                let mut layer = file_layer.clone();
+                layer.id = merged.layers.len() as u32;
+                layers.entry(file_layer.id).or_insert(layer.id);
+                merged.layers.push(layer);
+            }
+            let nodes = if file.scenegraph.is_empty() {
+                default_scene(file)
+            } else {
+                file.scenegraph.clone()
+            };
+            let ids: HashMap<u32, u32> = nodes
+                .iter()
+                .enumerate()
+                .map(|(position, node)| (node.id(), next_node + position as u32))
+                .collect();
+            let children: HashSet<u32> = nodes.iter().flat_map(child_ids).copied().collect();
+            for node in &nodes {
+                if !children.contains(&node.id()) {
+                    root.children.push(ids[&node.id()]);
+                }
+                scenegraph.push(renumber(node, &ids, &models, &layers));
+            }
+            next_node += nodes.len() as u32;
+        }
+        merged.scenegraph.push(SceneNode::Transform(TransformNode {
+            id: 0,
+            attrib: Dict::new(),
+            child_node_id: 1,
+            reserved_id: -1,
+            layer_id: u32::MAX,
+            frames: vec![Dict::new()],
+        }));
+        merged.scenegraph.push(SceneNode::Group(root));
+        merged.scenegraph.extend(scenegraph);
+        merged
+    }
+}
+
+/// Build a palette of at most 255 entries from the colors used by `files`, and a table per file
+/// mapping its palette indices to the new ones.
+fn unify_palettes(files: &[&VoxFile]) -> (Vec<Entry>, Vec<[u8; 256]>) {
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut counts: Vec<usize> = Vec::new();
+    let mut sources: Vec<[usize; 256]> = Vec::new();
+    for file in files {
+        let materials = file.material_table();
+        let mut counted = [0; 256];
+        for model in &file.models {
+            for voxel in &model.voxels {
+                counted[usize::from(voxel.i)] += 1;
+            }
+        }
+        let mut source = [usize::MAX; 256];
+        for index in 1..256 {
+            if counted[index] == 0 {
+                continue;
+            }
+            let entry = (file.palette[index].to_abgr(), materials[index].properties.clone());
+            let position = entries.iter().position(|existing| *existing == entry).unwrap_or_else(|| {
+                entries.push(entry);
+                counts.push(0);
+                entries.len() - 1
+            });
+            counts[position] += counted[index];
+            source[index] = position;
+        }
+        sources.push(source);
+    }
+
+    // Keep the most used entries, in their original order.
+    let mut kept: Vec<usize> = (0..entries.len()).collect();
+    if kept.len() > 255 {
+        kept.sort_by(|x, y| counts[*y].cmp(&counts[*x]).then(x.cmp(y)));
+        kept.truncate(255);
+        kept.sort_unstable();
+    }
+    let mut new_index = vec![0u8; entries.len()];
+    for (index, position) in kept.iter().zip(1..=255) {
+        new_index[*index] = position;
+    }
+    for position in 0..entries.len() {
+        if new_index[position] == 0 {
+            let nearest = kept
+                .iter()
+                .min_by_key(|kept| distance(entries[position].0, entries[**kept].0))
+                .map_or(0, |kept| new_index[*kept]);
+            new_index[position] = nearest;
+        }
+    }
+    let tables = sources
+        .iter()
+        .map(|source| {
+            let mut table = [0; 256];
+            for (entry, position) in table.iter_mut().zip(source.iter()) {
+                if let Some(index) = new_index.get(*position) {
+                    *entry = *index;
+                }
+            }
+            table
+        })
+        .collect();
+    let entries = kept.into_iter().map(|position| entries[position].clone()).collect();
+    (entries, tables)
+}
+
+/// Squared distance between two `0xAABBGGRR` colors.
+fn distance(a: u32, b: u32) -> u32 {
+    a.to_le_bytes()
+        .iter()
+        .zip(b.to_le_bytes().iter())
+        .map(|(a, b)| {
+            let delta = u32::from(a.max(b) - a.min(b));
+            delta * delta
+        })
+        .sum()
+}
+
+/// One Transform and Shape Node per model, for files without a scene graph.
+#[allow(clippy::cast_possible_truncation)]
+fn default_scene(file: &VoxFile) -> Vec<SceneNode> {
+    let mut nodes = Vec::new();
+    for position in 0..file.models.len() {
+        let id = position as u32 * 2;
+        nodes.push(SceneNode::Transform(TransformNode {
+            id,
+            attrib: Dict::new(),
+            child_node_id: id + 1,
+            reserved_id: -1,
+            layer_id: 0,
+            frames: vec![Dict::new()],
+        }));
+        nodes.push(SceneNode::Shape(ShapeNode {
+            id: id + 1,
+            attrib: Dict::new(),
+            models: vec![(position as u32, Dict::new())],
+        }));
+    }
+    nodes
+}
+
+/// Copy `node` with its ids, model ids and layer id moved to those of the merged file.
+///
+/// Each map takes the ids of the original file to those of the merged file. Ids missing from a
+/// map, such as dangling references, become `u32::MAX`.
+fn renumber(
+    node: &SceneNode,
+    ids: &HashMap<u32, u32>,
+    models: &HashMap<u32, u32>,
+    layers: &HashMap<u32, u32>,
+) -> SceneNode {
+    let lookup = |map: &HashMap<u32, u32>, id: &u32| map.get(id).copied().unwrap_or(u32::MAX);
+    let id = |id: &u32| lookup(ids, id);
+    match node {
+        SceneNode::Transform(node) => SceneNode::Transform(TransformNode {
+            id: id(&node.id),
+            child_node_id: id(&node.child_node_id),
+            layer_id: lookup(layers, &node.layer_id),
+            ..node.clone()
+        }),
+        SceneNode::Group(node) => SceneNode::Group(GroupNode {
+            id: id(&node.id),
+            attrib: node.attrib.clone(),
+            children: node.children.iter().map(id).collect(),
+        }),
+        SceneNode::Shape(node) => SceneNode::Shape(ShapeNode {
+            id: id(&node.id),
+            attrib: node.attrib.clone(),
+            models: node.models.iter().map(|(model, attrib)| (lookup(models, model), attrib.clone())).collect(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Dict, Layer, Model, SceneNode, Size, Voxel, VoxFile};
+
+    fn colors(file: &VoxFile) -> Vec<u32> {
+        file.models
+            .iter()
+            .flat_map(|model| model.voxels.iter())
+            .map(|voxel| file.color_of(voxel).to_abgr())
+            .collect()
+    }
+
+    #[test]
+    fn test_merge() {
+        let a = VoxFile::open("vox/chr_knight.vox").expect("Error loading test file.");
+        let b = VoxFile::open("vox/room.vox").expect("Error loading test file.");
+        let merged = VoxFile::merge(&a, &b);
+        assert_eq!(merged.models.len(), a.models.len() + b.models.len());
+        assert_eq!(merged.layers.len(), a.layers.len() + b.layers.len());
+        let mut expected = colors(&a);
+        expected.extend(colors(&b));
+        assert_eq!(colors(&merged), expected);
+
+        let graph = merged.scene_graph().expect("Valid scene graph");
+        assert_eq!(graph.root().map(crate::SceneNode::id), Some(0));
+        let instances = merged.instances().expect("Valid instances");
+        assert_eq!(instances.len(), a.instances().unwrap().len() + b.instances().unwrap().len());
+
+        let mut bytes = Vec::new();
+        merged.write_to(&mut bytes).expect("Error writing file.");
+        let reloaded = VoxFile::from_bytes(&bytes).expect("Error reloading file.");
+        assert_eq!(colors(&reloaded), expected);
+    }
+
+    #[test]
+    fn test_merge_overflow() {
+        let file = |green: u8| {
+            let mut file = VoxFile { models: Vec::new(), ..VoxFile::default() };
+            let mut voxels = Vec::new();
+            for i in 1..=255 {
+                file.palette[usize::from(i)].r = i;
+                file.palette[usize::from(i)].g = green;
+                voxels.push(Voxel { x: i, y: 0, z: 0, i });
+            }
+            file.models.push(Model { id: 0, size: Size { x: 256, y: 1, z: 1 }, voxels });
+            file
+        };
+        let merged = VoxFile::merge(&file(0), &file(1));
+        assert!(merged.models[0].voxels.iter().chain(&merged.models[1].voxels).all(|voxel| voxel.i != 0));
+        for (voxel, other) in merged.models[0].voxels.iter().zip(&merged.models[1].voxels) {
+            assert_eq!(merged.color_of(voxel).r, voxel.x);
+            assert_eq!(merged.color_of(other).r, other.x);
+        }
+        assert_eq!(merged.scene_graph().expect("Valid scene graph").len(), 2 + 4);
+    }
+
+    #[test]
+    fn test_merge_large_ids() {
+        let mut a = VoxFile::open("vox/chr_knight.vox").expect("Error loading test file.");
+        for node in &mut a.scenegraph {
+            if let SceneNode::Transform(node) = node {
+                if node.layer_id != u32::MAX {
+                    node.layer_id = u32::MAX - 1;
+                }
+            }
+        }
+        a.layers = vec![Layer { id: u32::MAX - 1, attributes: Dict::new(), reserved: -1 }];
+        let b = VoxFile::open("vox/room.vox").expect("Error loading test file.");
+        let merged = VoxFile::merge(&a, &b);
+        assert_eq!(merged.layers.len(), 1 + b.layers.len());
+        assert!(merged.layers.iter().enumerate().all(|(position, layer)| layer.id as usize == position));
+        let first = merged.instances().expect("Valid instances")[0].layer_id;
+        assert_eq!(first, 0);
+        assert!(merged.models.iter().enumerate().all(|(position, model)| model.id as usize == position));
+    }
+}
@@ -0,0 +1,448 @@
+//
+// Copyright 2021 Hans W. Uhlig. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use crate::error::{DotVoxError, DotVoxResult};
+use crate::material::{index_for_material_id, material_id_for_index};
+use crate::types::{Color, Material, VoxFile};
+use std::cmp::Ordering;
+
+/// Palette display order `MagicaVoxel` uses when a file has no IMAP chunk: indices 1 to 255,
+/// followed by 0.
+fn default_display_order() -> [u8; 256] {
+    let mut order = [0; 256];
+    for (slot, index) in order.iter_mut().zip(1..=255) {
+        *slot = index;
+    }
+    order
+}
+
+/// Table mapping every palette index to itself.
+pub(crate) fn identity_table() -> [u8; 256] {
+    let mut table = [0; 256];
+    for (entry, index) in table.iter_mut().zip(0..=255) {
+        *entry = index;
+    }
+    table
+}
+
+/// Number of colors in each row of the palette editor.
+pub const PALETTE_ROW_LENGTH: usize = 8;
+
+/// Number of rows in the palette editor, each of which can be named by a NOTE.
+pub const PALETTE_ROWS: usize = 32;
+
+/// A named row of the palette.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PaletteGroup<'a> {
+    /// Row of the palette editor, 0 being the first.
+    pub row: usize,
+    /// Name of the row, from the NOTE chunk.
+    pub name: &'a str,
+    /// Palette indices in this row, in display order.
+    pub indices: [u8; PALETTE_ROW_LENGTH],
+    /// Colors in this row, in display order.
+    pub colors: Vec<&'a Color>,
+}
+
+impl VoxFile {
+    /// Palette indices in the order `MagicaVoxel` shows them, taken from the IMAP chunk if the
+    /// file has one.
+    pub fn display_order(&self) -> [u8; 256] {
+        self.index_map.unwrap_or_else(default_display_order)
+    }
+
+    /// Set the order palette indices are shown in.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `order` doesn't contain every palette index exactly once.
+    pub fn set_display_order(&mut self, order: [u8; 256]) -> DotVoxResult<()> {
+        let mut seen = [false; 256];
+        for index in &order {
+            if std::mem::replace(&mut seen[usize::from(*index)], true) {
+                return Err(DotVoxError::InvalidIndexMap(*index));
+            }
+        }
+        self.index_map = Some(order);
+        Ok(())
+    }
+
+    /// The palette in display order.
+    pub fn display_palette(&self) -> Vec<&Color> {
+        self.display_order().iter().map(|index| &self.palette[usize::from(*index)]).collect()
+    }
+
+    /// The named rows of the palette.
+    pub fn palette_groups(&self) -> Vec<PaletteGroup<'_>> {
+        (0..PALETTE_ROWS).filter_map(|row| self.palette_row(row)).collect()
+    }
+
+    /// Find the palette row named `name`.
+    pub fn palette_group(&self, name: &str) -> Option<PaletteGroup<'_>> {
+        self.palette_groups().into_iter().find(|group| group.name == name)
+    }
+
+    /// Name palette row `row`, or remove its name if `name` is empty.
+    ///
+    /// Updates the notes and the name of every color in the row.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `row` is not less than [`PALETTE_ROWS`].
+    pub fn set_palette_group_name(&mut self, row: usize, name: &str) -> DotVoxResult<()> {
+        if row >= PALETTE_ROWS {
+            return Err(DotVoxError::InvalidAttribute { key: String::from("row"), value: row.to_string() });
+        }
+        if self.notes.len() <= row {
+            self.notes.resize(PALETTE_ROWS, String::new());
+        }
+        name.clone_into(&mut self.notes[row]);
+        self.apply_notes();
+        Ok(())
+    }
+
+    /// Set the name of every palette color to the note of the row it is shown in.
+    ///
+    /// Colors in rows without a note have no name. Called when reading a file.
+    pub fn apply_notes(&mut self) {
+        let order = self.display_order();
+        for (slot, index) in order.iter().enumerate() {
+            let name = self.notes.get(slot / PALETTE_ROW_LENGTH).filter(|name| !name.is_empty());
+            self.palette[usize::from(*index)].name = name.cloned();
+        }
+    }
+
+    fn palette_row(&self, row: usize) -> Option<PaletteGroup<'_>> {
+        let name = self.notes.get(row).filter(|name| !name.is_empty())?;
+        let order = self.display_order();
+        let mut indices = [0; PALETTE_ROW_LENGTH];
+        indices.copy_from_slice(&order[row * PALETTE_ROW_LENGTH..(row + 1) * PALETTE_ROW_LENGTH]);
+        let colors = indices.iter().map(|index| &self.palette[usize::from(*index)]).collect();
+        Some(PaletteGroup { row, name, indices, colors })
+    }
+
+    /// Swap the colors at palette indices `a` and `b`.
+    ///
+    /// Voxels and materials using either index are rewritten to keep their color, and the index
+    /// map is updated so the palette is still shown in the same order.
+    pub fn swap_palette_indices(&mut self, a: u8, b: u8) {
+        let mut table = identity_table();
+        table.swap(usize::from(a), usize::from(b));
+        self.remap_palette(&table);
+    }
+
+    /// Move palette index `i` to `table[i]`.
+    ///
+    /// Every voxel and material using index `i` is rewritten to use `table[i]`, and the color at
+    /// `i` is moved there. When several indices move to the same index, the color of the lowest
+    /// of them is kept, and indices nothing moves to keep their color. The index map is updated
+    /// so colors are still shown in the same order.
+    pub fn remap_palette(&mut self, table: &[u8; 256]) {
+        let palette = self.palette.clone();
+        for (index, color) in palette.iter().enumerate().rev() {
+            self.palette[usize::from(table[index])] = color.clone();
+        }
+        for model in &mut self.models {
+            for voxel in &mut model.voxels {
+                voxel.i = table[usize::from(voxel.i)];
+            }
+        }
+        let mut seen = [false; 256];
+        self.materials.retain(|material| {
+            let id = match material {
+                Material::V1(material) => material.id,
+                Material::V2(material) => material.id,
+            };
+            match index_for_material_id(id) {
+                Some(index) => !std::mem::replace(&mut seen[usize::from(table[usize::from(index)])], true),
+                None => true,
+            }
+        });
+        for material in &mut self.materials {
+            let id = match material {
+                Material::V1(material) => &mut material.id,
+                Material::V2(material) => &mut material.id,
+            };
+            if let Some(index) = index_for_material_id(*id) {
+                *id = material_id_for_index(table[usize::from(index)]);
+            }
+        }
+        let mut seen = [false; 256];
+        let mut order: Vec<u8> = self
+            .display_order()
+            .iter()
+            .map(|index| table[usize::from(*index)])
+            .filter(|index| !std::mem::replace(&mut seen[usize::from(*index)], true))
+            .collect();
+        order.extend(default_display_order().iter().filter(|index| !seen[usize::from(**index)]));
+        let mut index_map = [0; 256];
+        index_map.copy_from_slice(&order);
+        self.index_map = Some(index_map);
+        self.apply_notes();
+    }
+
+    /// Move the colors used by voxels to the start of the palette, keeping their relative order.
+    ///
+    /// Unused colors are moved after them and the display order is reset to index order, so the
+    /// used colors are shown first. Returns the number of colors used.
+    pub fn compact_palette(&mut self) -> usize {
+        let used = self.used_palette_indices();
+        let count = used.iter().skip(1).filter(|used| **used).count();
+        let mut table = [0; 256];
+        let mut next: u8 = 1;
+        for pass in &[true, false] {
+            for index in 1..256 {
+                if used[index] == *pass {
+                    table[index] = next;
+                    next = next.wrapping_add(1);
+                }
+            }
+        }
+        self.remap_palette(&table);
+        self.index_map = None;
+        self.apply_notes();
+        count
+    }
+
+    /// Make voxels with duplicate colors use the lowest index holding that color.
+    ///
+    /// Colors are only merged if their materials are the same too. The duplicate colors stay in
+    /// the palette unused, so [`VoxFile::compact_palette`] can reclaim them. Returns the number of
+    /// indices merged.
+    pub fn dedupe_palette(&mut self) -> usize {
+        let materials = self.material_table();
+        let mut table = identity_table();
+        let mut merged = 0;
+        for index in 2..256 {
+            let color = self.palette[index].to_abgr();
+            let original = (1..index).find(|other| {
+                self.palette[*other].to_abgr() == color && materials[*other].properties == materials[index].properties
+            });
+            if let Some(original) = original {
+                table[index] = table[original];
+                merged += 1;
+            }
+        }
+        for model in &mut self.models {
+            for voxel in &mut model.voxels {
+                voxel.i = table[usize::from(voxel.i)];
+            }
+        }
+        merged
+    }
+
+    /// Sort palette indices 1-255 by `key`, rewriting voxels and materials to keep their colors.
+    ///
+    /// The display order is reset to index order, so the palette is shown sorted.
+    pub fn sort_palette(&mut self, key: PaletteSortKey) {
+        let mut indices: Vec<usize> = (1..256).collect();
+        let compare = |a: f32, b: f32| a.partial_cmp(&b).unwrap_or(Ordering::Equal);
+        indices.sort_by(|a, b| {
+            let (a, b) = (&self.palette[*a], &self.palette[*b]);
+            match key {
+                PaletteSortKey::Hue => compare(hue(a), hue(b)).then(compare(luminance(a), luminance(b))),
+                PaletteSortKey::Luminance => compare(luminance(a), luminance(b)),
+            }
+        });
+        let mut table = identity_table();
+        for (index, new) in indices.iter().zip(1..=255) {
+            table[*index] = new;
+        }
+        self.remap_palette(&table);
+        self.index_map = None;
+        self.apply_notes();
+    }
+
+    /// Which palette indices are used by voxels.
+    pub fn used_palette_indices(&self) -> [bool; 256] {
+        let mut used = [false; 256];
+        for model in &self.models {
+            for voxel in &model.voxels {
+                used[usize::from(voxel.i)] = true;
+            }
+        }
+        used
+    }
+}
+
+/// Order to sort the palette in, see [`VoxFile::sort_palette`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaletteSortKey {
+    /// By hue, with grays first, then by luminance.
+    Hue,
+    /// By relative luminance, darkest first.
+    Luminance,
+}
+
+/// Hue of `color` in degrees, or -1 for grays.
+fn hue(color: &Color) -> f32 {
+    let (r, g, b) = (f32::from(color.r), f32::from(color.g), f32::from(color.b));
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    if delta == 0.0 {
+        return -1.0;
+    }
+    let hue = if (max - r).abs() < f32::EPSILON {
+        (g - b) / delta
+    } else if (max - g).abs() < f32::EPSILON {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+    (hue * 60.0).rem_euclid(360.0)
+}
+
+/// Relative luminance of `color`, ignoring gamma.
+fn luminance(color: &Color) -> f32 {
+    0.2126 * f32::from(color.r) + 0.7152 * f32::from(color.g) + 0.0722 * f32::from(color.b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{luminance, PaletteSortKey};
+    use crate::{DotVoxError, VoxFile};
+
+    fn voxel_colors(file: &VoxFile) -> Vec<(u8, u8, u8, u8)> {
+        file.models
+            .iter()
+            .flat_map(|model| model.voxels.iter())
+            .map(|voxel| file.color_of(voxel))
+            .map(|color| (color.r, color.g, color.b, color.a))
+            .collect()
+    }
+
+    #[test]
+    fn test_display_order() {
+        let file = VoxFile::open("vox/room.vox").expect("Error loading test file.");
+        let order = file.display_order();
+        assert_eq!(Some(order), file.index_map);
+        assert_eq!(file.display_palette()[0], &file.palette[usize::from(order[0])]);
+
+        let mut file = VoxFile::open("vox/3x3x3.vox").expect("Error loading test file.");
+        let order = file.display_order();
+        assert_eq!(order[0], 1);
+        assert_eq!(order[254], 255);
+        assert_eq!(order[255], 0);
+
+        let mut reversed = order;
+        reversed.reverse();
+        file.set_display_order(reversed).expect("Valid display order");
+        assert_eq!(file.index_map, Some(reversed));
+        reversed[0] = reversed[1];
+        assert!(matches!(file.set_display_order(reversed), Err(DotVoxError::InvalidIndexMap(_))));
+    }
+
+    #[test]
+    fn test_swap_keeps_display() {
+        let original = VoxFile::open("vox/room.vox").expect("Error loading test file.");
+        let mut file = original.clone();
+        let used = file.models[0].voxels[0].i;
+        let other = used.wrapping_add(1);
+        file.swap_palette_indices(used, other);
+        assert_eq!(file.display_palette(), original.display_palette());
+        assert_eq!(file.models[0].voxels[0].i, other);
+        assert_eq!(file.palette[usize::from(other)], original.palette[usize::from(used)]);
+
+        let mut bytes = Vec::new();
+        file.write_to(&mut bytes).expect("Error writing file.");
+        let reloaded = VoxFile::from_bytes(&bytes).expect("Error reloading file.");
+        assert_eq!(reloaded.display_palette(), original.display_palette());
+    }
+
+    #[test]
+    fn test_palette_groups() {
+        let mut file = VoxFile::open("vox/streetlamp.vox").expect("Error loading test file.");
+        let groups = file.palette_groups();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].name, "Row 1");
+        assert_eq!(groups[0].indices, [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(groups[1].indices, [9, 10, 11, 12, 13, 14, 15, 16]);
+        assert_eq!(file.palette[1].name.as_deref(), Some("Row 1"));
+        assert_eq!(file.palette[16].name.as_deref(), Some("Row 2"));
+        assert_eq!(file.palette[17].name, None);
+
+        file.set_palette_group_name(2, "foliage").expect("Error naming row.");
+        let group = file.palette_group("foliage").expect("Named group");
+        assert_eq!(group.row, 2);
+        assert!(group.colors.iter().all(|color| color.name.as_deref() == Some("foliage")));
+        file.set_palette_group_name(0, "").expect("Error naming row.");
+        assert!(file.palette_group("Row 1").is_none());
+        assert_eq!(file.palette[1].name, None);
+
+        let mut file = VoxFile::default();
+        file.set_palette_group_name(31, "last").expect("Error naming row.");
+        assert_eq!(file.notes.len(), 32);
+        assert_eq!(file.palette[0].name.as_deref(), Some("last"));
+        assert!(matches!(
+            file.set_palette_group_name(32, "past"),
+            Err(DotVoxError::InvalidAttribute { .. })
+        ));
+        assert_eq!(file.notes.len(), 32);
+    }
+
+    #[test]
+    fn test_compact_palette() {
+        let original = VoxFile::open("vox/chr_knight.vox").expect("Error loading test file.");
+        let mut file = original.clone();
+        let count = file.compact_palette();
+        assert_eq!(count, original.used_palette_indices().iter().skip(1).filter(|used| **used).count());
+        let used = file.used_palette_indices();
+        assert!(used[1..=count].iter().all(|used| *used));
+        assert!(used[count + 1..].iter().all(|used| !*used));
+        assert_eq!(voxel_colors(&file), voxel_colors(&original));
+    }
+
+    #[test]
+    fn test_dedupe_palette() {
+        let mut file = VoxFile::open("vox/3x3x3.vox").expect("Error loading test file.");
+        let used = file.models[0].voxels[0].i;
+        let duplicate = if used == 255 { 254 } else { 255 };
+        file.palette[usize::from(duplicate)] = file.palette[usize::from(used)].clone();
+        file.swap_palette_indices(used, duplicate);
+        assert_eq!(file.models[0].voxels[0].i, duplicate);
+        let original = voxel_colors(&file);
+        assert!(file.dedupe_palette() >= 1);
+        assert_eq!(file.models[0].voxels[0].i, used.min(duplicate));
+        assert_eq!(voxel_colors(&file), original);
+    }
+
+    #[test]
+    fn test_sort_palette() {
+        let original = VoxFile::open("vox/monu1.vox").expect("Error loading test file.");
+        let mut file = original.clone();
+        file.sort_palette(PaletteSortKey::Luminance);
+        assert!(file.palette[1..].windows(2).all(|pair| luminance(&pair[0]) <= luminance(&pair[1])));
+        assert_eq!(voxel_colors(&file), voxel_colors(&original));
+        assert_eq!(file.index_map, None);
+
+        let mut file = original.clone();
+        file.sort_palette(PaletteSortKey::Hue);
+        assert_eq!(voxel_colors(&file), voxel_colors(&original));
+    }
+
+    #[test]
+    fn test_remap_merges() {
+        let original = VoxFile::open("vox/3x3x3.vox").expect("Error loading test file.");
+        let mut file = original.clone();
+        let mut table = super::identity_table();
+        table[200] = 100;
+        file.remap_palette(&table);
+        assert_eq!(file.palette[100], original.palette[100]);
+        let order = file.display_order();
+        let mut seen = [false; 256];
+        assert!(order.iter().all(|index| !std::mem::replace(&mut seen[usize::from(*index)], true)));
+    }
+}
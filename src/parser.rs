@@ -18,18 +18,13 @@
 
 use nom::{
     IResult,
-    bytes::complete::{
-        take,
-        tag,
-    },
+    bytes::complete::take,
     combinator::{
         map,
         map_res,
     },
-    multi::{
-        count,
-        many0,
-    },
+    error::{ErrorKind, FromExternalError, ParseError},
+    Parser,
     number::complete::{
         le_u8,
         le_u32,
@@ -38,65 +33,266 @@ use nom::{
     },
     sequence::tuple,
 };
-use crate::types::*;
-use std::iter::FromIterator;
-use std::convert::TryInto;
+use crate::error::{DotVoxError, DotVoxResult, Limit, ReadWarning};
+use crate::types::{
+    MAGIC_NUMBER, Camera, Chunk, Color, Dict, GroupNode, Layer, Material, MaterialV1, MaterialV2,
+    Model, Pack, SceneNode, ShapeNode, Size, TransformNode, Voxel, VoxFile,
+};
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::io::Read;
+use std::path::Path;
 
-const MAGIC_NUMBER: &'static str = "VOX ";
+/// Options controlling how a .vox file is read.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReadOptions {
+    /// Replace legacy MATT materials with their MATL equivalent while loading.
+    pub upgrade_materials: bool,
+    /// Recover from damaged files instead of failing.
+    ///
+    /// Chunks that extend past the end of their parent are truncated, and chunks whose content
+    /// can't be parsed are skipped, salvaging the complete voxels and colors of short XYZI and
+    /// RGBA chunks. Each problem is recorded in [`VoxFile::warnings`].
+    pub lenient: bool,
+    /// Limits on the size of the file, enforced even when reading leniently.
+    pub limits: Limits,
+}
 
-#[derive(Debug)]
-#[allow(non_camel_case_types)]
-pub enum Chunk {
-    /// Main Chunk, No Content, Many Children
-    MAIN(Vec<Chunk>),
-    /// Size Chunk, Multiple Content
-    SIZE(Size),
-    /// XYZI Chunk,
-    XYZI(Vec<Voxel>),
-    /// Pack Chunk
-    PACK(Pack),
-    /// Color Palette
-    RGBA(Vec<Color>),
-    /// V1 Material
-    MATT(MaterialV1),
-    /// V2 Material
-    MATL(MaterialV2),
-    /// Unknown
-    rOBJ(Dict),
-    /// Unknown
-    rCAM(Camera),
-    /// Unknown
-    IMAP(Vec<u8>),
-    /// Unknown
-    NOTE(Vec<String>),
-    /// Scene Graph Transform Node
-    nTRN(TransformNode),
-    /// Scene Graph Group Node
-    nGRP(GroupNode),
-    /// Scene Graph Shape Node
-    nSHP(ShapeNode),
-    /// Layer Node
-    LAYR(Layer),
-    /// Unknown Node
-    Unknown {
-        kind: String,
-        contents: Vec<u8>,
-        children: Vec<Chunk>,
-    },
+/// Limits on files being read, protecting against untrusted input exhausting memory or time.
+///
+/// Files exceeding a limit fail to read with [`DotVoxError::LimitExceeded`]. The defaults are
+/// well beyond what `MagicaVoxel` itself produces.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[allow(clippy::struct_field_names)]
+pub struct Limits {
+    /// Maximum size of the file in bytes.
+    pub max_file_size: usize,
+    /// Maximum number of models.
+    pub max_models: usize,
+    /// Maximum number of voxels in a model.
+    pub max_voxels_per_model: usize,
+    /// Maximum number of entries in a dictionary, or of names in a NOTE chunk.
+    pub max_dict_entries: usize,
+    /// Maximum length of a string in bytes.
+    pub max_string_length: usize,
+    /// Maximum number of chunks a chunk can be nested in.
+    pub max_nesting_depth: usize,
+    /// Maximum number of children of a Group Node.
+    pub max_group_children: usize,
+    /// Maximum number of models, one per keyframe, of a Shape Node.
+    pub max_shape_models: usize,
+    /// Maximum number of keyframes of a Transform Node.
+    pub max_transform_frames: usize,
 }
 
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_file_size: 256 * 1024 * 1024,
+            max_models: 65_536,
+            max_voxels_per_model: 256 * 256 * 256,
+            max_dict_entries: 1024,
+            max_string_length: 65_536,
+            max_nesting_depth: 64,
+            max_group_children: 65_536,
+            max_shape_models: 4096,
+            max_transform_frames: 4096,
+        }
+    }
+}
+
+impl VoxFile {
+    /// Parse a .vox file from a byte slice.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bytes are not a well formed .vox file.
+    pub fn from_bytes(bytes: &[u8]) -> DotVoxResult<VoxFile> {
+        VoxFile::from_bytes_with(bytes, &ReadOptions::default())
+    }
+
+    /// Parse a .vox file from a byte slice using `options`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bytes are not a well formed .vox file.
+    pub fn from_bytes_with(bytes: &[u8], options: &ReadOptions) -> DotVoxResult<VoxFile> {
+        let mut file = parse_file(bytes, options)?;
+        if options.upgrade_materials {
+            file.upgrade_materials();
+        }
+        Ok(file)
+    }
+
+    /// Read and parse a .vox file from any reader.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading fails or the data is not a well formed .vox file.
+    pub fn from_reader<R: Read>(reader: R) -> DotVoxResult<VoxFile> {
+        VoxFile::from_reader_with(reader, &ReadOptions::default())
+    }
+
+    /// Read and parse a .vox file from any reader using `options`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading fails or the data is not a well formed .vox file.
+    pub fn from_reader_with<R: Read>(reader: R, options: &ReadOptions) -> DotVoxResult<VoxFile> {
+        // Read one byte past the limit, so larger files are rejected without reading all of them.
+        let limit = u64::try_from(options.limits.max_file_size.saturating_add(1)).unwrap_or(u64::MAX);
+        let mut bytes = Vec::new();
+        reader.take(limit).read_to_end(&mut bytes)?;
+        VoxFile::from_bytes_with(&bytes, options)
+    }
+
+    /// Open and parse the .vox file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or is not a well formed .vox file.
+    pub fn open<P: AsRef<Path>>(path: P) -> DotVoxResult<VoxFile> {
+        VoxFile::open_with(path, &ReadOptions::default())
+    }
+
+    /// Open and parse the .vox file at `path` using `options`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or is not a well formed .vox file.
+    pub fn open_with<P: AsRef<Path>>(path: P, options: &ReadOptions) -> DotVoxResult<VoxFile> {
+        VoxFile::from_reader_with(std::fs::File::open(path)?, options)
+    }
+}
+
+/// Newest file version this crate understands.
+const MAX_VERSION: u32 = 200;
+
+/// State shared by the chunk parsers.
+struct Context<'a> {
+    /// The whole file, so errors can report offsets into it.
+    file: &'a [u8],
+    options: &'a ReadOptions,
+    warnings: Vec<ReadWarning>,
+    /// Number of chunks enclosing the one being parsed.
+    depth: usize,
+    /// Number of XYZI chunks parsed so far.
+    models: usize,
+    /// Path and offset of each scene node chunk in MAIN, in file order.
+    scene_locations: Vec<(String, usize)>,
+}
+
+impl Context<'_> {
+    /// Record `err` as a warning if reading leniently, otherwise return it.
+    fn recover(&mut self, err: DotVoxError) -> DotVoxResult<()> {
+        if self.options.lenient {
+            tracing::warn!("Recovering from {}", err);
+            self.warnings.push(ReadWarning::from(&err));
+            Ok(())
+        } else {
+            Err(err)
+        }
+    }
+
+    /// Byte offset of `slice` within the file.
+    fn offset_of(&self, slice: &[u8]) -> usize {
+        // Slices handed to the parsers all point into the file, so locate them by address.
+        (slice.as_ptr() as usize).saturating_sub(self.file.as_ptr() as usize).min(self.file.len())
+    }
+}
+
+/// Fail with [`DotVoxError::LimitExceeded`] if `value` exceeds `max`.
+fn check_chunk_limit(limit: Limit, path: &str, offset: usize, value: usize, max: usize) -> DotVoxResult<()> {
+    if value > max {
+        Err(DotVoxError::LimitExceeded { limit, path: path.to_owned(), offset, value, max })
+    } else {
+        Ok(())
+    }
+}
+
+/// Parse the chunks of a .vox file from a byte slice, without interpreting them.
+///
+/// Returns the file version and the top level chunks, normally a single MAIN chunk holding all
+/// the others. Chunks are kept in file order, with those this crate doesn't understand stored as
+/// [`Chunk::Unknown`], so [`write_chunks`](crate::write_chunks) writes the same file back. So are
+/// chunks holding more content than their format, or children, such as a SIZE chunk with extra
+/// bytes.
+///
+/// # Errors
+///
+/// Returns an error if the bytes are not a well formed .vox file.
+pub fn read_chunks(bytes: &[u8]) -> DotVoxResult<(u32, Vec<Chunk>)> {
+    let (version, chunks, _) = read_chunks_with(bytes, &ReadOptions::default())?;
+    Ok((version, chunks))
+}
+
+/// Parse the chunks of a .vox file from a byte slice using `options`, without interpreting them.
+///
+/// See [`read_chunks`]. Only the limits and lenient reading apply. Also returns the problems
+/// recovered from when reading leniently, as [`VoxFile::warnings`] does for whole files.
+///
+/// # Errors
+///
+/// Returns an error if the bytes are not a well formed .vox file.
+pub fn read_chunks_with(bytes: &[u8], options: &ReadOptions) -> DotVoxResult<(u32, Vec<Chunk>, Vec<ReadWarning>)> {
+    let mut context =
+        Context { file: bytes, options, warnings: Vec::new(), depth: 0, models: 0, scene_locations: Vec::new() };
+    let version = parse_header(&mut context, bytes)?;
+    let mut chunks = Vec::new();
+    let mut siblings = HashMap::new();
+    let mut input = &bytes[8..];
+    while !input.is_empty() {
+        let (rest, chunk) = parse_chunk(&mut context, input, "", &mut siblings)?;
+        chunks.extend(chunk);
+        input = rest;
+    }
+    Ok((version, chunks, context.warnings))
+}
+
+/// Check the size and header of the file, returning its version.
+fn parse_header(context: &mut Context, input: &[u8]) -> DotVoxResult<u32> {
+    check_chunk_limit(Limit::FileSize, "", 0, input.len(), context.options.limits.max_file_size)?;
+    if !input.starts_with(MAGIC_NUMBER.as_bytes()) {
+        return Err(DotVoxError::BadMagic);
+    }
+    let version = match input.get(4..8) {
+        Some(version) => u32::from_le_bytes([version[0], version[1], version[2], version[3]]),
+        None => {
+            return Err(DotVoxError::TruncatedChunk {
+                id: MAGIC_NUMBER.to_owned(),
+                path: String::new(),
+                offset: 0,
+                expected: 8,
+                available: input.len(),
+            })
+        }
+    };
+    if version > MAX_VERSION {
+        context.recover(DotVoxError::UnsupportedVersion(version))?;
+    }
+    Ok(version)
+}
 
-#[tracing::instrument]
-fn parse_file(input: &[u8]) -> IResult<&[u8], VoxFile> {
+#[tracing::instrument(skip(input))]
+fn parse_file(input: &[u8], options: &ReadOptions) -> DotVoxResult<VoxFile> {
     tracing::trace!("parse_file(len: {})", input.len());
-    let (input, _) = tag(MAGIC_NUMBER)(input)?;
-    let (input, _version) = le_u32(input)?;
-    let (input, main) = parse_chunk(input)?;
-    if let Chunk::MAIN(children) = main {
-        let mut file = VoxFile::default();
+    let mut context =
+        Context { file: input, options, warnings: Vec::new(), depth: 0, models: 0, scene_locations: Vec::new() };
+    let version = parse_header(&mut context, input)?;
+    let (_, main) = parse_chunk(&mut context, &input[8..], "", &mut HashMap::new())?;
+    // A MAIN chunk with content of its own is kept whole, but its children are still read.
+    let main = match main {
+        Some(Chunk::Unknown { kind, children, .. }) if kind == "MAIN" => Some(Chunk::MAIN(children)),
+        main => main,
+    };
+    if let Some(Chunk::MAIN(children)) = main {
+        let mut file = VoxFile { version, ..VoxFile::default() };
         let mut model_id = 0;
         let mut model_size = None;
-        for child in children {
+        for (index, child) in children.into_iter().enumerate() {
+            if !matches!(child, Chunk::Unknown { .. }) {
+                file.chunk_order.push(child.id().to_owned());
+            }
             match child {
                 Chunk::MAIN(_) => {
                     tracing::error!("Attempting to process a MAIN chunk inside a MAIN Chunk");
@@ -109,88 +305,287 @@ fn parse_file(input: &[u8]) -> IResult<&[u8], VoxFile> {
                         file.models.push(Model { id: model_id, size, voxels });
                         model_id += 1;
                         model_size = None;
-
                     }
                 }
                 Chunk::PACK(pack) => {
                     tracing::debug!("Got a pack with value of: {:?}", pack);
+                    file.pack = true;
                 }
                 Chunk::RGBA(colors) => {
-                    file.palette = colors.try_into().unwrap();
+                    // File slot 0 holds the color of palette index 1, and slot 255 that of index 0.
+                    for (slot, color) in colors.into_iter().take(256).enumerate() {
+                        file.palette[(slot + 1) % 256] = color;
+                    }
                 }
                 Chunk::MATT(material) => {
-                    file.materials.push(Material::V1(material))
+                    file.materials.push(Material::V1(material));
                 }
                 Chunk::MATL(material) => {
-                    file.materials.push(Material::V2(material))
+                    file.materials.push(Material::V2(material));
                 }
-                Chunk::rOBJ(_obj) => {}
-                Chunk::rCAM(_cam) => {}
-                Chunk::IMAP(_imap) => {}
-                Chunk::NOTE(_note) => {}
-                Chunk::nTRN(_transform) => {}
-                Chunk::nGRP(_group) => {}
-                Chunk::nSHP(_shape) => {}
-                Chunk::LAYR(_layer) => {}
-                Chunk::Unknown { .. } => {}
+                Chunk::rOBJ(object) => file.render_objects.push(object),
+                Chunk::rCAM(camera) => file.cameras.push(camera),
+                Chunk::IMAP(index_map) => file.index_map = index_map.try_into().ok(),
+                Chunk::NOTE(notes) => file.notes = notes,
+                Chunk::nTRN(node) => file.scenegraph.push(SceneNode::Transform(node)),
+                Chunk::nGRP(node) => file.scenegraph.push(SceneNode::Group(node)),
+                Chunk::nSHP(node) => file.scenegraph.push(SceneNode::Shape(node)),
+                Chunk::LAYR(layer) => file.layers.push(layer),
+                chunk @ Chunk::Unknown { .. } => file.unknown_chunks.push((index, chunk)),
             }
         }
-        Ok((input, file))
+        file.apply_notes();
+        file.scene_locations = context.scene_locations;
+        file.warnings = context.warnings;
+        Ok(file)
     } else {
-        Err(nom::Err::Error(nom::error::make_error(input, nom::error::ErrorKind::Eof)))
+        Err(DotVoxError::NoMainChunk)
     }
 }
 
-#[tracing::instrument]
-fn parse_chunk(input: &[u8]) -> IResult<&[u8], Chunk> {
-    let (input, kind) = map_res(take(4usize), std::str::from_utf8)(input)?;
-    let (input, content_size) = le_u32(input)?;
-    let (input, children_size) = le_u32(input)?;
-    let (input, chunk_content) = take(content_size)(input)?;
-    let (input, child_content) = take(children_size)(input)?;
+/// Parse the chunk at the start of `input`, returning the input after it.
+///
+/// `parent` is the path of the enclosing chunk, empty for the top level chunk, and `siblings`
+/// counts the chunks of each id already parsed in it, so the chunk's path can be reported in
+/// errors. When reading leniently a chunk whose content can't be parsed is skipped and `None`
+/// is returned in its place. Errors are only returned for chunks whose extent can't be
+/// determined.
+#[tracing::instrument(skip(context, input, siblings))]
+fn parse_chunk<'a>(
+    context: &mut Context<'a>,
+    input: &'a [u8],
+    parent: &str,
+    siblings: &mut HashMap<String, usize>,
+) -> DotVoxResult<(&'a [u8], Option<Chunk>)> {
+    let offset = context.offset_of(input);
+    let id = String::from_utf8_lossy(&input[..input.len().min(4)]).into_owned();
+    let path = if parent.is_empty() {
+        id.clone()
+    } else {
+        let sibling = siblings.entry(id.clone()).or_insert(0);
+        *sibling += 1;
+        format!("{}/{}[{}]", parent, id, *sibling - 1)
+    };
+    let truncated = |expected: usize| DotVoxError::TruncatedChunk {
+        id: id.clone(),
+        path: path.clone(),
+        offset,
+        expected,
+        available: input.len(),
+    };
+    let (input, (kind, content_size, children_size)) =
+        tuple((take(4usize), le_u32, le_u32))(input).map_err(|_: nom::Err<()>| truncated(12))?;
+    let mut content_size = content_size as usize;
+    let mut children_size = children_size as usize;
+    if content_size.saturating_add(children_size) > input.len() {
+        context.recover(truncated(content_size.saturating_add(children_size).saturating_add(12)))?;
+        content_size = content_size.min(input.len());
+        children_size = children_size.min(input.len() - content_size);
+    }
+    let (chunk_content, input) = input.split_at(content_size);
+    let (child_content, input) = input.split_at(children_size);
+    let Ok(kind) = std::str::from_utf8(kind) else {
+        // The chunk's extent is still known, so only it is lost.
+        context.recover(DotVoxError::InvalidUtf8 { path, offset })?;
+        return Ok((input, None));
+    };
     tracing::trace!(
         "parse_chunk({}, Content Size: {}, Child Size: {})",
         kind, content_size, children_size
     );
-    println!("{} Content: {} Children Size: {}", kind, content_size, children_size);
-    let children = if children_size > 0 {
-        many0(parse_chunk)(child_content)?.1
+    let limits = &context.options.limits;
+    if !child_content.is_empty() {
+        let offset = offset + 12 + content_size;
+        check_chunk_limit(Limit::NestingDepth, &path, offset, context.depth + 1, limits.max_nesting_depth)?;
+    }
+    if kind == "XYZI" {
+        context.models += 1;
+        check_chunk_limit(Limit::Models, &path, offset, context.models, limits.max_models)?;
+    }
+    let mut children = Vec::new();
+    let mut child_siblings = HashMap::new();
+    let mut remaining = child_content;
+    context.depth += 1;
+    while !remaining.is_empty() {
+        match parse_chunk(context, remaining, &path, &mut child_siblings) {
+            Ok((rest, child)) => {
+                children.extend(child);
+                remaining = rest;
+            }
+            Err(err @ DotVoxError::LimitExceeded { .. }) => return Err(err),
+            Err(err) => {
+                // The rest of the children can't be located, so give up on them.
+                context.recover(err)?;
+                break;
+            }
+        }
+    }
+    context.depth -= 1;
+    if kind == "RGBA" && chunk_content.len() < 256 * 4 {
+        context.recover(DotVoxError::BadPaletteLength {
+            path: path.clone(),
+            offset: offset + 12,
+            length: chunk_content.len(),
+        })?;
+    }
+    let chunk = match parse_content(kind, chunk_content, children, limits) {
+        Ok(chunk) => Some(chunk),
+        Err(err) => {
+            let err = content_error(context, &path, chunk_content, err);
+            if let DotVoxError::LimitExceeded { .. } = err {
+                return Err(err);
+            }
+            context.recover(err)?;
+            if kind == "XYZI" {
+                Some(Chunk::XYZI(salvage_XYZI(chunk_content)))
+            } else {
+                None
+            }
+        }
+    };
+    if context.depth == 1 && matches!(chunk, Some(Chunk::nTRN(_) | Chunk::nGRP(_) | Chunk::nSHP(_))) {
+        context.scene_locations.push((path, offset));
+    }
+    Ok((input, chunk))
+}
+
+/// Convert an error parsing the content of the chunk at `path` into a [`DotVoxError`].
+fn content_error(context: &Context, path: &str, chunk_content: &[u8], err: nom::Err<ContentError>) -> DotVoxError {
+    let path = path.to_owned();
+    match err {
+        nom::Err::Error(err) | nom::Err::Failure(err) => match err {
+            ContentError::Nom(input, ErrorKind::MapRes) => {
+                DotVoxError::InvalidUtf8 { path, offset: context.offset_of(input) }
+            }
+            ContentError::Nom(input, kind) => DotVoxError::MalformedChunk {
+                path,
+                offset: context.offset_of(input),
+                reason: kind.description().to_owned(),
+            },
+            ContentError::Limit { input, limit, value, max } => {
+                DotVoxError::LimitExceeded { limit, path, offset: context.offset_of(input), value, max }
+            }
+        },
+        nom::Err::Incomplete(_) => DotVoxError::MalformedChunk {
+            path,
+            offset: context.offset_of(chunk_content) + chunk_content.len(),
+            reason: String::from("Incomplete"),
+        },
+    }
+}
+
+/// Error parsing the content of a chunk.
+#[derive(Debug)]
+enum ContentError<'a> {
+    /// The content doesn't match the format of the chunk.
+    Nom(&'a [u8], ErrorKind),
+    /// The content exceeds one of the [`Limits`].
+    Limit { input: &'a [u8], limit: Limit, value: usize, max: usize },
+}
+
+impl<'a> ParseError<&'a [u8]> for ContentError<'a> {
+    fn from_error_kind(input: &'a [u8], kind: ErrorKind) -> Self {
+        ContentError::Nom(input, kind)
+    }
+
+    fn append(_: &'a [u8], _: ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+impl<'a, E> FromExternalError<&'a [u8], E> for ContentError<'a> {
+    fn from_external_error(input: &'a [u8], kind: ErrorKind, _: E) -> Self {
+        ContentError::Nom(input, kind)
+    }
+}
+
+type ContentResult<'a, O> = IResult<&'a [u8], O, ContentError<'a>>;
+
+/// Fail without trying alternatives if `value` exceeds `max`.
+fn check_limit(input: &[u8], limit: Limit, value: usize, max: usize) -> Result<(), nom::Err<ContentError<'_>>> {
+    if value > max {
+        Err(nom::Err::Failure(ContentError::Limit { input, limit, value, max }))
     } else {
-        Vec::new()
+        Ok(())
+    }
+}
+
+/// Parse the content of a chunk of type `kind`.
+fn parse_content<'a>(
+    kind: &str,
+    input: &'a [u8],
+    children: Vec<Chunk>,
+    limits: &Limits,
+) -> Result<Chunk, nom::Err<ContentError<'a>>> {
+    let unknown = |children| Chunk::Unknown { kind: kind.to_owned(), contents: Vec::from(input), children };
+    let (rest, chunk) = match kind {
+        "MAIN" if input.is_empty() => return Ok(Chunk::MAIN(children)),
+        "PACK" => parse_PACK(input).map(|(rest, pack)| (rest, Chunk::PACK(pack)))?,
+        "SIZE" => parse_SIZE(input).map(|(rest, size)| (rest, Chunk::SIZE(size)))?,
+        "XYZI" => parse_XYZI(input, limits).map(|(rest, voxels)| (rest, Chunk::XYZI(voxels)))?,
+        "RGBA" => parse_RGBA(input).map(|(rest, colors)| (rest, Chunk::RGBA(colors)))?,
+        "MATT" => parse_MATT(input).map(|(rest, material)| (rest, Chunk::MATT(material)))?,
+        "MATL" => parse_MATL(input, limits).map(|(rest, material)| (rest, Chunk::MATL(material)))?,
+        "rOBJ" => parse_rOBJ(input, limits).map(|(rest, object)| (rest, Chunk::rOBJ(object)))?,
+        "rCAM" => parse_rCAM(input, limits).map(|(rest, camera)| (rest, Chunk::rCAM(camera)))?,
+        "IMAP" => parse_IMAP(input).map(|(rest, index_map)| (rest, Chunk::IMAP(index_map)))?,
+        "NOTE" => parse_NOTE(input, limits).map(|(rest, notes)| (rest, Chunk::NOTE(notes)))?,
+        "nTRN" => parse_nTRN(input, limits).map(|(rest, node)| (rest, Chunk::nTRN(node)))?,
+        "nGRP" => parse_nGRP(input, limits).map(|(rest, node)| (rest, Chunk::nGRP(node)))?,
+        "nSHP" => parse_nSHP(input, limits).map(|(rest, node)| (rest, Chunk::nSHP(node)))?,
+        "LAYR" => parse_LAYR(input, limits).map(|(rest, layer)| (rest, Chunk::LAYR(layer)))?,
+        _ => return Ok(unknown(children)),
     };
-    Ok((input, match kind {
-        "MAIN" => Chunk::MAIN(children),
-        "PACK" => Chunk::PACK(parse_PACK(chunk_content)?.1),
-        "SIZE" => Chunk::SIZE(parse_SIZE(chunk_content)?.1),
-        "XYZI" => Chunk::XYZI(parse_XYZI(chunk_content)?.1),
-        "RGBA" => Chunk::RGBA(parse_RGBA(chunk_content)?.1),
-        "MATT" => Chunk::MATT(parse_MATT(chunk_content)?.1),
-        "MATL" => Chunk::MATL(parse_MATL(chunk_content)?.1),
-        "rOBJ" => Chunk::rOBJ(parse_rOBJ(chunk_content)?.1),
-        "rCAM" => Chunk::rCAM(parse_rCAM(chunk_content)?.1),
-        "IMAP" => Chunk::IMAP(parse_IMAP(chunk_content)?.1),
-        "NOTE" => Chunk::NOTE(parse_NOTE(chunk_content)?.1),
-        "nTRN" => Chunk::nTRN(parse_nTRN(chunk_content)?.1),
-        "nGRP" => Chunk::nGRP(parse_nGRP(chunk_content)?.1),
-        "nSHP" => Chunk::nSHP(parse_nSHP(chunk_content)?.1),
-        "LAYR" => Chunk::LAYR(parse_LAYR(chunk_content)?.1),
-        _ => Chunk::Unknown {
-            kind: kind.to_owned(),
-            contents: Vec::from(chunk_content),
-            children,
+    // Known chunks with more content than their format or with children are kept whole, so
+    // writing them back loses nothing.
+    if rest.is_empty() && children.is_empty() {
+        Ok(chunk)
+    } else {
+        Ok(unknown(children))
+    }
+}
+
+/// The complete voxels of an XYZI chunk with fewer voxels than it claims.
+fn salvage_XYZI(input: &[u8]) -> Vec<Voxel> {
+    let voxels = input.get(4..).unwrap_or(&[]);
+    voxels
+        .chunks_exact(4)
+        .map(|voxel| Voxel { x: voxel[0], y: voxel[1], z: voxel[2], i: voxel[3] })
+        .collect()
+}
+
+/// Most items [`count`] reserves space for up front.
+const MAX_RESERVED_ITEMS: usize = 4096;
+
+/// Apply `parser` `count` times, like `nom::multi::count`.
+///
+/// Unlike that, space is only reserved for a bounded number of items, so a huge count read from
+/// a damaged file fails to parse instead of exhausting memory. The items actually parsed are
+/// bounded by the size of the input.
+fn count<'a, O, F>(mut parser: F, count: usize) -> impl FnMut(&'a [u8]) -> ContentResult<'a, Vec<O>>
+where
+    F: Parser<&'a [u8], O, ContentError<'a>>,
+{
+    move |mut input: &'a [u8]| {
+        let mut items = Vec::with_capacity(count.min(MAX_RESERVED_ITEMS));
+        for _ in 0..count {
+            let (rest, item) = parser.parse(input)?;
+            items.push(item);
+            input = rest;
         }
-    }))
+        Ok((input, items))
+    }
 }
 
-#[tracing::instrument]
-fn parse_PACK(input: &[u8]) -> IResult<&[u8], Pack> {
+#[tracing::instrument(skip_all, fields(len = input.len()))]
+fn parse_PACK(input: &[u8]) -> ContentResult<'_, Pack> {
     tracing::trace!("parse_PACK(len: {})", input.len());
     let (input, model_count) = le_u32(input)?;
     Ok((input, Pack(model_count)))
 }
 
-#[tracing::instrument]
-fn parse_SIZE(input: &[u8]) -> IResult<&[u8], Size> {
+#[tracing::instrument(skip_all, fields(len = input.len()))]
+fn parse_SIZE(input: &[u8]) -> ContentResult<'_, Size> {
     tracing::trace!("parse_SIZE(len: {})", input.len());
     let (input, x) = le_u32(input)?;
     let (input, y) = le_u32(input)?;
@@ -198,10 +593,11 @@ fn parse_SIZE(input: &[u8]) -> IResult<&[u8], Size> {
     Ok((input, Size { x, y, z }))
 }
 
-#[tracing::instrument]
-fn parse_XYZI(input: &[u8]) -> IResult<&[u8], Vec<Voxel>> {
+#[tracing::instrument(skip_all, fields(len = input.len()))]
+fn parse_XYZI<'a>(input: &'a [u8], limits: &Limits) -> ContentResult<'a, Vec<Voxel>> {
     tracing::trace!("parse_XYZI(len: {})", input.len());
     let (input, voxel_count) = le_u32(input)?;
+    check_limit(input, Limit::VoxelsPerModel, voxel_count as usize, limits.max_voxels_per_model)?;
     let (input, voxels) = count(|input| {
         let (input, x) = le_u8(input)?;
         let (input, y) = le_u8(input)?;
@@ -212,46 +608,49 @@ fn parse_XYZI(input: &[u8]) -> IResult<&[u8], Vec<Voxel>> {
     Ok((input, voxels))
 }
 
-#[tracing::instrument]
-fn parse_RGBA(input: &[u8]) -> IResult<&[u8], Vec<Color>> {
+#[tracing::instrument(skip_all, fields(len = input.len()))]
+fn parse_RGBA(input: &[u8]) -> ContentResult<'_, Vec<Color>> {
     tracing::trace!("parse_RGBA(len: {})", input.len());
+    // Short palettes are rejected before parsing unless reading leniently.
     let (input, colors) = count(|input| {
         let (input, r) = le_u8(input)?;
         let (input, g) = le_u8(input)?;
         let (input, b) = le_u8(input)?;
         let (input, a) = le_u8(input)?;
         Ok((input, Color { name: None, r, g, b, a }))
-    }, 256)(input)?;
+    }, (input.len() / 4).min(256))(input)?;
+    // A partial color at the end of a short palette is dropped along with the missing ones.
+    let input = if colors.len() < 256 { &input[input.len()..] } else { input };
     Ok((input, colors))
 }
 
-#[tracing::instrument]
-fn parse_MATT(input: &[u8]) -> IResult<&[u8], MaterialV1> {
+#[tracing::instrument(skip_all, fields(len = input.len()))]
+fn parse_MATT(input: &[u8]) -> ContentResult<'_, MaterialV1> {
     tracing::trace!("parse_MATT(len: {})", input.len());
     let (input, id) = le_u32(input)?;
     let (input, kind) = le_u32(input)?;
     let (input, weight) = le_f32(input)?;
     let (input, property_bits) = le_u32(input)?;
     let (input, plastic) = if (property_bits & 0x01) != 0 {
-        map(le_f32, |v| Some(v))(input)
+        map(le_f32, Some)(input)
     } else { Ok((input, None)) }?;
     let (input, roughness) = if (property_bits & 0x02) != 0 {
-        map(le_f32, |v| Some(v))(input)
+        map(le_f32, Some)(input)
     } else { Ok((input, None)) }?;
     let (input, specular) = if (property_bits & 0x04) != 0 {
-        map(le_f32, |v| Some(v))(input)
+        map(le_f32, Some)(input)
     } else { Ok((input, None)) }?;
     let (input, ior) = if (property_bits & 0x08) != 0 {
-        map(le_f32, |v| Some(v))(input)
+        map(le_f32, Some)(input)
     } else { Ok((input, None)) }?;
     let (input, attenuation) = if (property_bits & 0x10) != 0 {
-        map(le_f32, |v| Some(v))(input)
+        map(le_f32, Some)(input)
     } else { Ok((input, None)) }?;
     let (input, power) = if (property_bits & 0x20) != 0 {
-        map(le_f32, |v| Some(v))(input)
+        map(le_f32, Some)(input)
     } else { Ok((input, None)) }?;
     let (input, glow) = if (property_bits & 0x40) != 0 {
-        map(le_f32, |v| Some(v))(input)
+        map(le_f32, Some)(input)
     } else { Ok((input, None)) }?;
     let (input, is_total_power) = if (property_bits & 0x80) != 0 {
         (input, true)
@@ -259,138 +658,507 @@ fn parse_MATT(input: &[u8]) -> IResult<&[u8], MaterialV1> {
     Ok((input, MaterialV1 { id, kind, weight, plastic, roughness, specular, ior, attenuation, power, glow, is_total_power }))
 }
 
-#[tracing::instrument]
-fn parse_MATL(input: &[u8]) -> IResult<&[u8], MaterialV2> {
+#[tracing::instrument(skip_all, fields(len = input.len()))]
+fn parse_MATL<'a>(input: &'a [u8], limits: &Limits) -> ContentResult<'a, MaterialV2> {
     tracing::trace!("parse_MATL(len: {})", input.len());
     let (input, id) = le_u32(input)?;
-    let (input, properties) = parse_DICT(input)?;
+    let (input, properties) = parse_DICT(input, limits)?;
     Ok((input, MaterialV2 { id, properties }))
 }
 
-#[tracing::instrument]
-fn parse_rOBJ(input: &[u8]) -> IResult<&[u8], Dict> {
+#[tracing::instrument(skip_all, fields(len = input.len()))]
+fn parse_rOBJ<'a>(input: &'a [u8], limits: &Limits) -> ContentResult<'a, Dict> {
     tracing::trace!("parse_rOBJ(len: {})", input.len());
-    parse_DICT(input)
+    parse_DICT(input, limits)
 }
 
-#[tracing::instrument]
-fn parse_rCAM(input: &[u8]) -> IResult<&[u8], Camera> {
+#[tracing::instrument(skip_all, fields(len = input.len()))]
+fn parse_rCAM<'a>(input: &'a [u8], limits: &Limits) -> ContentResult<'a, Camera> {
     tracing::trace!("parse_rCAM(len: {})", input.len());
     let (input, id) = le_u32(input)?;
-    let (input, attributes) = parse_DICT(input)?;
+    let (input, attributes) = parse_DICT(input, limits)?;
     Ok((input, Camera { id, attributes }))
 }
 
-#[tracing::instrument]
-fn parse_IMAP(input: &[u8]) -> IResult<&[u8], Vec<u8>> {
+#[tracing::instrument(skip_all, fields(len = input.len()))]
+fn parse_IMAP(input: &[u8]) -> ContentResult<'_, Vec<u8>> {
     tracing::trace!("parse_IMAP(len: {})", input.len());
     count(le_u8, 256)(input)
 }
 
-#[tracing::instrument]
-fn parse_NOTE(input: &[u8]) -> IResult<&[u8], Vec<String>> {
+#[tracing::instrument(skip_all, fields(len = input.len()))]
+fn parse_NOTE<'a>(input: &'a [u8], limits: &Limits) -> ContentResult<'a, Vec<String>> {
     tracing::trace!("parse_NOTE(len: {})", input.len());
     let (input, string_count) = le_u32(input)?;
-    count(parse_STRING, string_count as usize)(input)
+    check_limit(input, Limit::DictEntries, string_count as usize, limits.max_dict_entries)?;
+    count(|input| parse_STRING(input, limits), string_count as usize)(input)
 }
 
-#[tracing::instrument]
-fn parse_nTRN(input: &[u8]) -> IResult<&[u8], TransformNode> {
+#[tracing::instrument(skip_all, fields(len = input.len()))]
+fn parse_nTRN<'a>(input: &'a [u8], limits: &Limits) -> ContentResult<'a, TransformNode> {
     tracing::trace!("parse_nTRN(len: {})", input.len());
     let (input, id) = le_u32(input)?;
-    let (input, attrib) = parse_DICT(input)?;
+    let (input, attrib) = parse_DICT(input, limits)?;
     let (input, child_node_id) = le_u32(input)?;
     let (input, reserved_id) = le_i32(input)?;
     let (input, layer_id) = le_u32(input)?;
     let (input, frame_count) = le_u32(input)?;
+    check_limit(input, Limit::TransformFrames, frame_count as usize, limits.max_transform_frames)?;
     let (input, frames) = count(|input| {
-        let (input, attrib) = parse_DICT(input)?;
+        let (input, attrib) = parse_DICT(input, limits)?;
         Ok((input, attrib))
     }, frame_count as usize)(input)?;
     Ok((input, TransformNode { id, attrib, child_node_id, reserved_id, layer_id, frames }))
 }
 
-#[tracing::instrument]
-fn parse_nGRP(input: &[u8]) -> IResult<&[u8], GroupNode> {
+#[tracing::instrument(skip_all, fields(len = input.len()))]
+fn parse_nGRP<'a>(input: &'a [u8], limits: &Limits) -> ContentResult<'a, GroupNode> {
     tracing::trace!("parse_nGRP(len: {})", input.len());
     let (input, id) = le_u32(input)?;
-    let (input, attrib) = parse_DICT(input)?;
+    let (input, attrib) = parse_DICT(input, limits)?;
     let (input, child_node_count) = le_u32(input)?;
+    check_limit(input, Limit::GroupChildren, child_node_count as usize, limits.max_group_children)?;
     let (input, children) = count(le_u32, child_node_count as usize)(input)?;
     Ok((input, GroupNode { id, attrib, children }))
 }
 
-#[tracing::instrument]
-fn parse_nSHP(input: &[u8]) -> IResult<&[u8], ShapeNode> {
+#[tracing::instrument(skip_all, fields(len = input.len()))]
+fn parse_nSHP<'a>(input: &'a [u8], limits: &Limits) -> ContentResult<'a, ShapeNode> {
     tracing::trace!("parse_nSHP(len: {})", input.len());
     let (input, id) = le_u32(input)?;
-    let (input, attrib) = parse_DICT(input)?;
+    let (input, attrib) = parse_DICT(input, limits)?;
     let (input, model_count) = le_u32(input)?;
+    check_limit(input, Limit::ShapeModels, model_count as usize, limits.max_shape_models)?;
     let (input, models) = count(|input| {
         let (input, id) = le_u32(input)?;
-        let (input, attrib) = parse_DICT(input)?;
+        let (input, attrib) = parse_DICT(input, limits)?;
         Ok((input, (id, attrib)))
     }, model_count as usize)(input)?;
     Ok((input, ShapeNode { id, attrib, models }))
 }
 
-#[tracing::instrument]
-fn parse_LAYR(input: &[u8]) -> IResult<&[u8], Layer> {
+#[tracing::instrument(skip_all, fields(len = input.len()))]
+fn parse_LAYR<'a>(input: &'a [u8], limits: &Limits) -> ContentResult<'a, Layer> {
     tracing::trace!("parse_LAYR(len: {})", input.len());
     let (input, id) = le_u32(input)?;
-    let (input, attrib) = parse_DICT(input)?;
+    let (input, attrib) = parse_DICT(input, limits)?;
     let (input, reserved) = le_i32(input)?;
     Ok((input, Layer { id, attributes: attrib, reserved }))
 }
 
-#[tracing::instrument]
-fn parse_DICT(input: &[u8]) -> IResult<&[u8], Dict> {
+#[tracing::instrument(skip_all, fields(len = input.len()))]
+fn parse_DICT<'a>(input: &'a [u8], limits: &Limits) -> ContentResult<'a, Dict> {
     tracing::trace!("parse_DICT(len: {})", input.len());
     let (input, entry_count) = le_u32(input)?;
-    let (input, entries) = count(tuple((parse_STRING, parse_STRING)), entry_count as usize)(input)?;
-    Ok((input, Dict::from_iter(entries)))
+    check_limit(input, Limit::DictEntries, entry_count as usize, limits.max_dict_entries)?;
+    let string = |input| parse_STRING(input, limits);
+    let (input, entries) = count(tuple((string, string)), entry_count as usize)(input)?;
+    Ok((input, Dict::from_entries(entries)))
 }
 
-#[tracing::instrument]
-fn parse_STRING(input: &[u8]) -> IResult<&[u8], String> {
+#[tracing::instrument(skip_all, fields(len = input.len()))]
+fn parse_STRING<'a>(input: &'a [u8], limits: &Limits) -> ContentResult<'a, String> {
     tracing::trace!("parse_STRING(len: {})", input.len());
     let (input, bytes) = le_u32(input)?;
+    check_limit(input, Limit::StringLength, bytes as usize, limits.max_string_length)?;
     let (input, buffer) = map_res(take(bytes), std::str::from_utf8)(input)?;
     Ok((input, String::from(buffer)))
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::{Chunk, DotVoxError, Layer, Limit, Limits, ReadOptions, SceneNode, Voxel, VoxFile};
 
     //#[test]
     fn test_3x3x3() {
         println!("Loading 3x3x3.vox");
         let file = std::fs::read("vox/3x3x3.vox").expect("Error opening test file.");
-        let result = super::parse_file(&file);
-        println!("{:#?}", result)
+        let result = super::parse_file(&file, &ReadOptions::default());
+        println!("{result:#?}");
     }
 
     //#[test]
     fn test_8x8x8() {
         println!("Loading 8x8x8.vox");
         let file = std::fs::read("vox/8x8x8.vox").expect("Error opening test file.");
-        let result = super::parse_file(&file);
-        println!("{:#?}", result)
+        let result = super::parse_file(&file, &ReadOptions::default());
+        println!("{result:#?}");
     }
 
     //#[test]
     fn test_menger() {
         println!("Loading menger.vox");
         let file = std::fs::read("vox/menger.vox").expect("Error opening test file.");
-        let result = super::parse_file(&file);
-        println!("{:?}", result)
+        let result = super::parse_file(&file, &ReadOptions::default());
+        println!("{result:?}");
     }
 
     #[test]
     fn test_streelamp() {
         println!("Loading streetlamp.vox");
         let file = std::fs::read("vox/streetlamp.vox").expect("Error opening test file.");
-        let result = super::parse_file(&file);
+        let result = super::parse_file(&file, &ReadOptions::default());
         //println!("{:#?}", result)
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_open() {
+        let file = VoxFile::open("vox/T-Rex.vox").expect("Error loading test file.");
+        assert_eq!(file.version, 150);
+        assert_eq!(file.models.len(), 8);
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let reader = std::fs::File::open("vox/3x3x3.vox").expect("Error opening test file.");
+        let file = VoxFile::from_reader(reader).expect("Error loading test file.");
+        assert_eq!(file.models.len(), 1);
+        assert_eq!(file.models[0].voxels.len(), 20);
+    }
+
+    #[test]
+    fn test_scene() {
+        let file = VoxFile::open("vox/streetlamp.vox").expect("Error loading test file.");
+        assert_eq!(file.scenegraph.len(), 6);
+        let shapes: Vec<u32> = file.scenegraph.iter().filter_map(|node| match node {
+            SceneNode::Shape(shape) => Some(shape.models[0].0),
+            _ => None,
+        }).collect();
+        assert_eq!(shapes, vec![0, 1]);
+        match file.scene_node(4) {
+            Some(SceneNode::Transform(node)) => {
+                assert_eq!(node.name(), Some("street_lamp"));
+                assert_eq!(node.child_node_id, 5);
+                assert_eq!(node.frames[0].get("_t"), Some("0 0 128"));
+                assert!(file.layer(node.layer_id).is_some());
+            }
+            other => panic!("Unexpected node: {:?}", other),
+        }
+        assert_eq!(file.layers.len(), 8);
+        assert_eq!(file.layer(3).and_then(Layer::name), Some("3"));
+        assert_eq!(file.cameras.len(), 10);
+        assert_eq!(file.camera(0).and_then(|camera| camera.attributes.get("_mode")), Some("pers"));
+        assert_eq!(file.render_objects.len(), 15);
+        assert_eq!(&file.notes[0..2], &["Row 1", "Row 2"]);
+    }
+
+    #[test]
+    fn test_palette_shift() {
+        let bytes = std::fs::read("vox/3x3x3.vox").expect("Error reading test file.");
+        let file = VoxFile::from_bytes(&bytes).expect("Error loading test file.");
+        let start = bytes.windows(4).position(|window| window == b"RGBA").expect("RGBA chunk") + 12;
+        for voxel in &file.models[0].voxels {
+            let slot = start + (usize::from(voxel.i) - 1) * 4;
+            let color = file.color_of(voxel);
+            assert_eq!([color.r, color.g, color.b, color.a], bytes[slot..slot + 4]);
+        }
+        let last = start + 255 * 4;
+        let color = &file.palette[0];
+        assert_eq!([color.r, color.g, color.b, color.a], bytes[last..last + 4]);
+    }
+
+    #[test]
+    fn test_index_map() {
+        let file = VoxFile::open("vox/room.vox").expect("Error loading test file.");
+        assert!(file.index_map.is_some());
+        assert!(!file.scenegraph.is_empty());
+        let file = VoxFile::open("vox/monu10.vox").expect("Error loading test file.");
+        assert!(file.index_map.is_none());
+        assert_eq!(file.scenegraph.len(), 4);
+        assert_eq!(file.layers.len(), 8);
+    }
+
+    /// Bytes of a chunk with `id`, `content` and `children`.
+    #[allow(clippy::cast_possible_truncation)]
+    fn chunk(id: &[u8], content: &[u8], children: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend_from_slice(&(content.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(children.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(content);
+        bytes.extend_from_slice(children);
+        bytes
+    }
+
+    /// Bytes of a version 150 file holding a MAIN chunk with `children`.
+    fn file(children: &[u8]) -> Vec<u8> {
+        let mut bytes = b"VOX \x96\0\0\0".to_vec();
+        bytes.extend(chunk(b"MAIN", &[], children));
+        bytes
+    }
+
+    #[test]
+    fn test_bad_magic() {
+        assert!(matches!(VoxFile::from_bytes(b"RIFF\x96\0\0\0"), Err(DotVoxError::BadMagic)));
+        assert!(matches!(VoxFile::from_bytes(b"VOX \xFF\0\0\0"), Err(DotVoxError::UnsupportedVersion(255))));
+        assert!(matches!(VoxFile::from_bytes(&file(&[])[..8]), Err(DotVoxError::TruncatedChunk { .. })));
+        let mut bytes = b"VOX \x96\0\0\0".to_vec();
+        bytes.extend(chunk(b"PACK", &[1, 0, 0, 0], &[]));
+        assert!(matches!(VoxFile::from_bytes(&bytes), Err(DotVoxError::NoMainChunk)));
+    }
+
+    #[test]
+    fn test_truncated() {
+        let file = std::fs::read("vox/3x3x3.vox").expect("Error opening test file.");
+        match VoxFile::from_bytes(&file[..file.len() / 2]) {
+            Err(DotVoxError::TruncatedChunk { id, path, offset, expected, available }) => {
+                assert_eq!((id.as_str(), path.as_str(), offset), ("MAIN", "MAIN", 8));
+                assert_eq!(expected, file.len() - 8);
+                assert_eq!(available, file.len() / 2 - 8);
+            }
+            other => panic!("Unexpected result: {:?}", other.map(|file| file.version)),
+        }
+    }
+
+    #[test]
+    fn test_error_path() {
+        let mut bytes = std::fs::read("vox/streetlamp.vox").expect("Error opening test file.");
+        // Claim the third nTRN chunk is far larger than it is.
+        let offset = bytes
+            .windows(4)
+            .enumerate()
+            .filter(|(_, window)| *window == b"nTRN")
+            .nth(2)
+            .map(|(offset, _)| offset)
+            .expect("Three nTRN chunks");
+        bytes[offset + 4..offset + 8].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = VoxFile::from_bytes(&bytes).map(|file| file.version).unwrap_err();
+        assert!(matches!(err, DotVoxError::TruncatedChunk { .. }));
+        assert_eq!(err.path(), Some("MAIN/nTRN[2]"));
+        assert_eq!(err.offset(), Some(offset));
+        assert!(err.to_string().contains("MAIN/nTRN[2]"));
+    }
+
+    #[test]
+    fn test_invalid_content() {
+        let mut layer = vec![0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0xFF, 1, 0, 0, 0, b'1'];
+        layer.extend_from_slice(&[0, 0, 0, 0]);
+        let bytes = file(&chunk(b"LAYR", &layer, &[]));
+        match VoxFile::from_bytes(&bytes) {
+            Err(DotVoxError::InvalidUtf8 { path, offset }) => {
+                assert_eq!(path, "MAIN/LAYR[0]");
+                assert_eq!(offset, 8 + 12 + 12 + 12);
+            }
+            other => panic!("Unexpected result: {:?}", other.map(|file| file.version)),
+        }
+
+        let bytes = file(&chunk(b"RGBA", &[0; 8], &[]));
+        match VoxFile::from_bytes(&bytes) {
+            Err(DotVoxError::BadPaletteLength { path, length, .. }) => {
+                assert_eq!(path, "MAIN/RGBA[0]");
+                assert_eq!(length, 8);
+            }
+            other => panic!("Unexpected result: {:?}", other.map(|file| file.version)),
+        }
+
+        let bytes = file(&[chunk(b"SIZE", &[1; 12], &[]), chunk(b"XYZI", &[9, 0, 0, 0, 1, 1, 1, 1], &[])].concat());
+        match VoxFile::from_bytes(&bytes) {
+            Err(DotVoxError::MalformedChunk { path, .. }) => assert_eq!(path, "MAIN/XYZI[0]"),
+            other => panic!("Unexpected result: {:?}", other.map(|file| file.version)),
+        }
+    }
+
+    #[test]
+    fn test_lenient() {
+        let lenient = ReadOptions { lenient: true, ..ReadOptions::default() };
+        let bytes = std::fs::read("vox/3x3x3.vox").expect("Error reading test file.");
+        let complete = VoxFile::from_bytes(&bytes).expect("Error loading test file.");
+        assert!(complete.warnings.is_empty());
+        let recovered = VoxFile::from_bytes_with(&bytes, &lenient).expect("Error loading test file.");
+        assert!(recovered.warnings.is_empty());
+        assert_eq!(recovered.models, complete.models);
+
+        // Cut the file off part way through the voxels, truncating both MAIN and XYZI and leaving
+        // XYZI with fewer voxels than it claims.
+        let start = bytes.windows(4).position(|window| window == b"XYZI").expect("XYZI chunk") + 16;
+        let truncated = &bytes[..start + 10 * 4 + 2];
+        assert!(VoxFile::from_bytes(truncated).is_err());
+        let recovered = VoxFile::from_bytes_with(truncated, &lenient).expect("Error recovering test file.");
+        assert_eq!(recovered.models.len(), 1);
+        assert_eq!(recovered.models[0].voxels, complete.models[0].voxels[..10]);
+        assert_eq!(recovered.palette, VoxFile::default().palette);
+        let paths: Vec<&str> = recovered.warnings.iter().map(|warning| warning.path.as_str()).collect();
+        assert_eq!(paths, vec!["MAIN", "MAIN/XYZI[0]", "MAIN/XYZI[0]"]);
+        assert_eq!(recovered.warnings[0].offset, Some(8));
+
+        // A chunk with an invalid id is skipped, and its siblings kept.
+        let bytes = std::fs::read("vox/T-Rex.vox").expect("Error reading test file.");
+        let complete = VoxFile::from_bytes(&bytes).expect("Error loading test file.");
+        let mut damaged = bytes.clone();
+        let start = bytes.windows(4).position(|window| window == b"SIZE").expect("SIZE chunk");
+        damaged[start] = 0xFF;
+        assert!(matches!(VoxFile::from_bytes(&damaged), Err(DotVoxError::InvalidUtf8 { .. })));
+        let recovered = VoxFile::from_bytes_with(&damaged, &lenient).expect("Error recovering test file.");
+        assert_eq!(recovered.models.len(), complete.models.len() - 1);
+        for (model, expected) in recovered.models.iter().zip(&complete.models[1..]) {
+            assert_eq!(model.voxels, expected.voxels);
+        }
+        assert_eq!(recovered.palette, complete.palette);
+        assert_eq!(recovered.warnings.len(), 1);
+        assert_eq!(recovered.warnings[0].offset, Some(start));
+
+        // Damaged chunks are skipped, and the chunks after them kept.
+        let mut layer = vec![0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0xFF, 1, 0, 0, 0, b'1'];
+        layer.extend_from_slice(&[0, 0, 0, 0]);
+        let bytes = file(&[chunk(b"LAYR", &layer, &[]), chunk(b"RGBA", &[1, 2, 3, 4, 5, 6], &[])].concat());
+        let recovered = VoxFile::from_bytes_with(&bytes, &lenient).expect("Error recovering test file.");
+        assert!(recovered.layers.is_empty());
+        assert_eq!(recovered.palette[1].to_abgr(), 0x0403_0201);
+        assert_eq!(recovered.palette[2], VoxFile::default().palette[2]);
+        assert_eq!(recovered.warnings.len(), 2);
+        assert_eq!(recovered.warnings[0].path, "MAIN/LAYR[0]");
+        assert_eq!(recovered.warnings[1].path, "MAIN/RGBA[0]");
+    }
+
+    #[test]
+    fn test_leftover_content() {
+        let size = chunk(b"SIZE", &[1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 9, 9, 9, 9], &[]);
+        let pack = chunk(b"PACK", &[1, 0, 0, 0], &chunk(b"zTST", &[1], &[]));
+        let rgba = chunk(b"RGBA", &[7; 256 * 4], &[]);
+        let children = [size, pack, rgba].concat();
+        let mut with_content = b"VOX \x96\0\0\0".to_vec();
+        with_content.extend(chunk(b"MAIN", &[1, 2], &children));
+        for bytes in &[file(&children), with_content] {
+            let (version, chunks) = crate::read_chunks(bytes).expect("Error reading chunks.");
+            let kinds: Vec<(&str, bool)> = chunks[0]
+                .children()
+                .iter()
+                .map(|chunk| (chunk.id(), matches!(chunk, Chunk::Unknown { .. })))
+                .collect();
+            assert_eq!(kinds, vec![("SIZE", true), ("PACK", true), ("RGBA", false)]);
+            let mut written = Vec::new();
+            crate::write_chunks(&mut written, version, &chunks).expect("Error writing chunks.");
+            assert_eq!(&written, bytes);
+
+            let file = VoxFile::from_bytes(bytes).expect("Error loading file.");
+            assert_eq!(file.unknown_chunks.len(), 2);
+            assert_eq!(file.palette[1].to_abgr(), 0x0707_0707);
+        }
+    }
+
+    #[test]
+    fn test_read_chunks_lenient() {
+        let size = chunk(b"SIZE", &[1; 12], &[]);
+        let mut xyzi = chunk(b"XYZI", &[2, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2], &[]);
+        xyzi.truncate(xyzi.len() - 2);
+        let bytes = file(&[size.clone(), xyzi].concat());
+        assert!(crate::read_chunks(&bytes).is_err());
+
+        let lenient = ReadOptions { lenient: true, ..ReadOptions::default() };
+        let (version, chunks, warnings) = crate::read_chunks_with(&bytes, &lenient).expect("Error recovering test file.");
+        assert_eq!(version, 150);
+        let children = chunks[0].children();
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[1], Chunk::XYZI(vec![Voxel { x: 1, y: 1, z: 1, i: 1 }]));
+        let paths: Vec<&str> = warnings.iter().map(|warning| warning.path.as_str()).collect();
+        assert_eq!(paths, vec!["MAIN/XYZI[0]", "MAIN/XYZI[0]"]);
+        assert_eq!(warnings[0].offset, Some(8 + 12 + size.len()));
+    }
+
+    #[test]
+    fn test_duplicate_dict_keys() {
+        let mut dict = 2u32.to_le_bytes().to_vec();
+        for value in &[b"1", b"2"] {
+            dict.extend_from_slice(&[2, 0, 0, 0, b'_', b'k', 1, 0, 0, 0]);
+            dict.extend_from_slice(*value);
+        }
+        let bytes = file(&chunk(b"rOBJ", &dict, &[]));
+        let file = VoxFile::from_bytes(&bytes).expect("Error reading test file.");
+        assert_eq!(file.render_objects[0].len(), 2);
+        assert_eq!(file.render_objects[0].get("_k"), Some("1"));
+        let mut written = Vec::new();
+        file.write_to(&mut written).expect("Error writing file.");
+        assert!(written.ends_with(&dict));
+
+        let (version, chunks) = crate::read_chunks(&bytes).expect("Error reading test file.");
+        let mut written = Vec::new();
+        crate::write_chunks(&mut written, version, &chunks).expect("Error writing file.");
+        assert!(written == bytes);
+    }
+
+    #[test]
+    fn test_hostile_input() {
+        let lenient = ReadOptions { lenient: true, ..ReadOptions::default() };
+
+        // Counts far larger than the chunk.
+        let bytes = file(&[chunk(b"SIZE", &[1; 12], &[]), chunk(b"XYZI", &1_000_000u32.to_le_bytes(), &[])].concat());
+        assert!(matches!(VoxFile::from_bytes(&bytes), Err(DotVoxError::MalformedChunk { .. })));
+        let bytes = file(&chunk(b"rOBJ", &1_000u32.to_le_bytes(), &[]));
+        assert!(matches!(VoxFile::from_bytes(&bytes), Err(DotVoxError::MalformedChunk { .. })));
+
+        // Node counts far larger than a few megabytes of content, with and without limits.
+        let mut shape = vec![0; 8];
+        shape.extend_from_slice(&u32::MAX.to_le_bytes());
+        shape.resize(4 * 1024 * 1024, 0);
+        let bytes = file(&chunk(b"nSHP", &shape, &[]));
+        match VoxFile::from_bytes(&bytes) {
+            Err(DotVoxError::LimitExceeded { limit, .. }) => assert_eq!(limit, Limit::ShapeModels),
+            other => panic!("Unexpected result: {:?}", other.map(|file| file.version)),
+        }
+        let unlimited = Limits {
+            max_shape_models: usize::MAX,
+            max_transform_frames: usize::MAX,
+            ..Limits::default()
+        };
+        let options = ReadOptions { limits: unlimited, ..ReadOptions::default() };
+        assert!(matches!(VoxFile::from_bytes_with(&bytes, &options), Err(DotVoxError::MalformedChunk { .. })));
+        let mut transform = vec![0; 20];
+        transform.extend_from_slice(&u32::MAX.to_le_bytes());
+        transform.resize(4 * 1024 * 1024, 0);
+        let bytes = file(&chunk(b"nTRN", &transform, &[]));
+        match VoxFile::from_bytes(&bytes) {
+            Err(DotVoxError::LimitExceeded { limit, .. }) => assert_eq!(limit, Limit::TransformFrames),
+            other => panic!("Unexpected result: {:?}", other.map(|file| file.version)),
+        }
+        assert!(matches!(VoxFile::from_bytes_with(&bytes, &options), Err(DotVoxError::MalformedChunk { .. })));
+
+        // Chunks nested far deeper than any real file.
+        let nested = (0..1_000).fold(Vec::new(), |children, _| chunk(b"XXXX", &[], &children));
+        let bytes = file(&nested);
+        assert!(matches!(VoxFile::from_bytes(&bytes), Err(DotVoxError::LimitExceeded { .. })));
+        assert!(matches!(VoxFile::from_bytes_with(&bytes, &lenient), Err(DotVoxError::LimitExceeded { .. })));
+
+        // Every prefix of a sample file.
+        let bytes = std::fs::read("vox/streetlamp.vox").expect("Error reading test file.");
+        for end in (0..bytes.len()).step_by(7) {
+            assert!(VoxFile::from_bytes(&bytes[..end]).is_err());
+            let _ = VoxFile::from_bytes_with(&bytes[..end], &lenient);
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let read = |bytes: &[u8], limits: Limits| {
+            let options = ReadOptions { lenient: true, limits, ..ReadOptions::default() };
+            match VoxFile::from_bytes_with(bytes, &options) {
+                Err(DotVoxError::LimitExceeded { limit, path, value, max, .. }) => (limit, path, value, max),
+                other => panic!("Unexpected result: {:?}", other.map(|file| file.version)),
+            }
+        };
+        let bytes = std::fs::read("vox/streetlamp.vox").expect("Error reading test file.");
+        let file_size = Limits { max_file_size: 1000, ..Limits::default() };
+        assert_eq!(read(&bytes, file_size.clone()), (Limit::FileSize, String::new(), bytes.len(), 1000));
+        let options = ReadOptions { limits: file_size, ..ReadOptions::default() };
+        let reader = std::fs::File::open("vox/streetlamp.vox").expect("Error opening test file.");
+        assert!(matches!(VoxFile::from_reader_with(reader, &options), Err(DotVoxError::LimitExceeded { .. })));
+
+        let models = Limits { max_models: 1, ..Limits::default() };
+        assert_eq!(read(&bytes, models), (Limit::Models, String::from("MAIN/XYZI[1]"), 2, 1));
+        let strings = Limits { max_string_length: 4, ..Limits::default() };
+        assert_eq!(read(&bytes, strings).0, Limit::StringLength);
+        let entries = Limits { max_dict_entries: 1, ..Limits::default() };
+        assert_eq!(read(&bytes, entries).0, Limit::DictEntries);
+        let children = Limits { max_group_children: 0, ..Limits::default() };
+        assert_eq!(read(&bytes, children).0, Limit::GroupChildren);
+        let frames = Limits { max_transform_frames: 0, ..Limits::default() };
+        assert_eq!(read(&bytes, frames).0, Limit::TransformFrames);
+        let shape_models = Limits { max_shape_models: 0, ..Limits::default() };
+        assert_eq!(read(&bytes, shape_models).0, Limit::ShapeModels);
+        let depth = Limits { max_nesting_depth: 0, ..Limits::default() };
+        assert_eq!(read(&bytes, depth), (Limit::NestingDepth, String::from("MAIN"), 1, 0));
+
+        let voxels = VoxFile::open("vox/3x3x3.vox").expect("Error loading test file.").models[0].voxels.len();
+        let bytes = std::fs::read("vox/3x3x3.vox").expect("Error reading test file.");
+        let limits = Limits { max_voxels_per_model: voxels - 1, ..Limits::default() };
+        assert_eq!(read(&bytes, limits), (Limit::VoxelsPerModel, String::from("MAIN/XYZI[0]"), voxels, voxels - 1));
+    }
+}
@@ -0,0 +1,281 @@
+//
+// Copyright 2021 Hans W. Uhlig. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use crate::types::{Color, Voxel, VoxFile};
+
+/// A voxel with its own RGBA color, to be quantized into a palette.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TrueColorVoxel {
+    /// The X coordinate of the voxel.
+    pub x: u8,
+    /// The Y coordinate of the voxel.
+    pub y: u8,
+    /// The Z coordinate of the voxel.
+    pub z: u8,
+    /// Color of the voxel as (Red, Green, Blue, Alpha).
+    pub rgba: [u8; 4],
+}
+
+/// Reduces arbitrary voxel colors to a .vox palette.
+///
+/// Colors are first split into boxes by median cut, then refined with a few rounds of k-means.
+#[derive(Clone, Debug)]
+pub struct Quantizer {
+    /// Maximum number of colors in the palette, at most 255.
+    pub max_colors: usize,
+    /// Number of k-means rounds refining the median cut colors.
+    pub iterations: usize,
+    /// Colors that are kept exactly, as palette indices 1 onwards. The remaining indices are
+    /// chosen from the voxel colors.
+    pub fixed: Vec<Color>,
+}
+
+/// Result of [`Quantizer::quantize`].
+#[derive(Clone, Debug)]
+pub struct Quantized {
+    /// The palette, indexed by [`Voxel::i`]. Unused entries hold the default palette.
+    pub palette: [Color; 256],
+    /// Number of palette entries used, starting at index 1.
+    pub colors: usize,
+    /// The voxels, in input order, with their nearest palette index.
+    pub voxels: Vec<Voxel>,
+}
+
+impl Default for Quantizer {
+    fn default() -> Quantizer {
+        Quantizer { max_colors: 255, iterations: 8, fixed: Vec::new() }
+    }
+}
+
+impl Quantizer {
+    /// A quantizer that keeps the colors of `palette` at indices 1-255 and may only use those.
+    pub fn with_palette(palette: &[Color; 256]) -> Quantizer {
+        Quantizer { fixed: palette[1..].to_vec(), ..Quantizer::default() }
+    }
+
+    /// Build a palette for `voxels` and index them into it.
+    pub fn quantize(&self, voxels: &[TrueColorVoxel]) -> Quantized {
+        let mut weights: HashMap<[u8; 4], u64> = HashMap::new();
+        for voxel in voxels {
+            *weights.entry(voxel.rgba).or_insert(0) += 1;
+        }
+        let mut distinct: Vec<([u8; 4], u64)> = weights.into_iter().collect();
+        distinct.sort_unstable();
+
+        let fixed: Vec<[u8; 4]> = self.fixed.iter().take(255).map(rgba).collect();
+        let free = self.max_colors.min(255).saturating_sub(fixed.len());
+        let mut centers: Vec<[u8; 4]> = distinct
+            .iter()
+            .map(|(color, _)| *color)
+            .filter(|color| !fixed.contains(color))
+            .collect();
+        if centers.len() > free {
+            centers = median_cut(&distinct, free);
+            for _ in 0..self.iterations {
+                if !refine(&distinct, &fixed, &mut centers) {
+                    break;
+                }
+            }
+        }
+
+        let mut colors = fixed;
+        colors.extend(centers);
+        let mut palette = VoxFile::default().palette;
+        for (entry, color) in palette[1..].iter_mut().zip(&colors) {
+            *entry = Color { name: None, r: color[0], g: color[1], b: color[2], a: color[3] };
+        }
+        let mut nearest_index: HashMap<[u8; 4], u8> = HashMap::new();
+        let voxels = voxels
+            .iter()
+            .map(|voxel| {
+                let i = *nearest_index.entry(voxel.rgba).or_insert_with(|| {
+                    let position = nearest(&colors, voxel.rgba);
+                    position.and_then(|position| u8::try_from(position + 1).ok()).unwrap_or(0)
+                });
+                Voxel { x: voxel.x, y: voxel.y, z: voxel.z, i }
+            })
+            .collect();
+        Quantized { palette, colors: colors.len(), voxels }
+    }
+}
+
+fn rgba(color: &Color) -> [u8; 4] {
+    [color.r, color.g, color.b, color.a]
+}
+
+/// Squared distance between two colors.
+fn distance(a: [u8; 4], b: [u8; 4]) -> u32 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(a, b)| {
+            let delta = u32::from(a.max(b) - a.min(b));
+            delta * delta
+        })
+        .sum()
+}
+
+/// Position of the color in `colors` nearest to `color`.
+fn nearest(colors: &[[u8; 4]], color: [u8; 4]) -> Option<usize> {
+    colors
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, candidate)| distance(**candidate, color))
+        .map(|(position, _)| position)
+}
+
+/// Weighted mean of `colors`.
+#[allow(clippy::cast_possible_truncation)]
+fn mean(colors: &[([u8; 4], u64)]) -> [u8; 4] {
+    let total: u64 = colors.iter().map(|(_, weight)| weight).sum::<u64>().max(1);
+    let mut mean = [0; 4];
+    for (channel, value) in mean.iter_mut().enumerate() {
+        let sum: u64 = colors.iter().map(|(color, weight)| u64::from(color[channel]) * weight).sum();
+        *value = ((sum + total / 2) / total) as u8;
+    }
+    mean
+}
+
+/// Split `colors` into at most `count` boxes, each time halving the box with the widest channel
+/// at its weighted median, and return the mean color of each box.
+fn median_cut(colors: &[([u8; 4], u64)], count: usize) -> Vec<[u8; 4]> {
+    if count == 0 {
+        return Vec::new();
+    }
+    let mut boxes: Vec<Vec<([u8; 4], u64)>> = vec![colors.to_vec()];
+    while boxes.len() < count {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .map(|(position, colors)| {
+                let (channel, range) = widest_channel(colors);
+                (position, channel, range)
+            })
+            .max_by_key(|(_, _, range)| *range);
+        let (position, channel) = match widest {
+            Some((position, channel, range)) if range > 0 => (position, channel),
+            _ => break,
+        };
+        let mut colors = boxes.swap_remove(position);
+        colors.sort_unstable_by_key(|(color, _)| color[channel]);
+        let total: u64 = colors.iter().map(|(_, weight)| weight).sum();
+        let mut seen = 0;
+        let mut split = colors.len() / 2;
+        for (index, (_, weight)) in colors.iter().enumerate() {
+            seen += weight;
+            if seen * 2 >= total {
+                split = index + 1;
+                break;
+            }
+        }
+        let split = split.max(1).min(colors.len() - 1);
+        let upper = colors.split_off(split);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+    boxes.iter().map(|colors| mean(colors)).collect()
+}
+
+/// The channel `colors` spread over the most, and its range.
+fn widest_channel(colors: &[([u8; 4], u64)]) -> (usize, u8) {
+    (0..4)
+        .map(|channel| {
+            let values = colors.iter().map(|(color, _)| color[channel]);
+            let range = values.clone().max().unwrap_or(0) - values.min().unwrap_or(0);
+            (channel, range)
+        })
+        .max_by_key(|(_, range)| *range)
+        .unwrap_or((0, 0))
+}
+
+/// Move each center to the mean of the colors nearest to it. `fixed` colors attract colors but
+/// don't move. Returns whether any center moved.
+fn refine(colors: &[([u8; 4], u64)], fixed: &[[u8; 4]], centers: &mut [[u8; 4]]) -> bool {
+    let mut all = fixed.to_vec();
+    all.extend(centers.iter());
+    let mut clusters: Vec<Vec<([u8; 4], u64)>> = vec![Vec::new(); centers.len()];
+    for (color, weight) in colors {
+        if let Some(position) = nearest(&all, *color) {
+            if position >= fixed.len() {
+                clusters[position - fixed.len()].push((*color, *weight));
+            }
+        }
+    }
+    let mut moved = false;
+    for (center, cluster) in centers.iter_mut().zip(&clusters) {
+        if !cluster.is_empty() {
+            let mean = mean(cluster);
+            moved |= mean != *center;
+            *center = mean;
+        }
+    }
+    moved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Quantizer, TrueColorVoxel};
+    use crate::VoxFile;
+
+    fn gradient() -> Vec<TrueColorVoxel> {
+        let mut voxels = Vec::new();
+        for x in 0..32u8 {
+            for y in 0..32u8 {
+                voxels.push(TrueColorVoxel { x, y, z: 0, rgba: [x * 8, y * 8, 128, 255] });
+            }
+        }
+        voxels
+    }
+
+    #[test]
+    fn test_exact_colors() {
+        let voxels: Vec<TrueColorVoxel> = gradient().into_iter().take(200).collect();
+        let quantized = Quantizer::default().quantize(&voxels);
+        assert_eq!(quantized.colors, 200);
+        for (voxel, indexed) in voxels.iter().zip(&quantized.voxels) {
+            let color = &quantized.palette[usize::from(indexed.i)];
+            assert_eq!([color.r, color.g, color.b, color.a], voxel.rgba);
+            assert_eq!((indexed.x, indexed.y, indexed.z), (voxel.x, voxel.y, voxel.z));
+        }
+    }
+
+    #[test]
+    fn test_reduce_colors() {
+        let voxels = gradient();
+        let quantized = Quantizer::default().quantize(&voxels);
+        assert_eq!(quantized.colors, 255);
+        for (voxel, indexed) in voxels.iter().zip(&quantized.voxels) {
+            assert!(indexed.i >= 1);
+            let color = &quantized.palette[usize::from(indexed.i)];
+            assert!(super::distance([color.r, color.g, color.b, color.a], voxel.rgba) <= 2 * 16 * 16);
+        }
+    }
+
+    #[test]
+    fn test_fixed_palette() {
+        let file = VoxFile::default();
+        let quantized = Quantizer::with_palette(&file.palette).quantize(&gradient());
+        assert_eq!(quantized.colors, 255);
+        assert_eq!(quantized.palette[1..], file.palette[1..]);
+
+        let seeded = Quantizer { fixed: file.palette[1..11].to_vec(), ..Quantizer::default() };
+        let quantized = seeded.quantize(&gradient());
+        assert_eq!(quantized.palette[1..11], file.palette[1..11]);
+        assert_eq!(quantized.colors, 255);
+    }
+}
@@ -0,0 +1,493 @@
+//
+// Copyright 2021 Hans W. Uhlig. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use crate::error::{DotVoxError, DotVoxResult};
+use crate::types::{SceneNode, VoxFile};
+
+/// Row-major 4x4 affine transformation matrix.
+pub type Matrix = [[f32; 4]; 4];
+
+pub(crate) const IDENTITY: Matrix = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+/// A single placement of a model in the world.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShapeInstance {
+    /// ID of the Shape Node placing the model.
+    pub node_id: u32,
+    /// ID of the placed model.
+    pub model_id: u32,
+    /// Layer of the Transform Node directly above the Shape Node.
+    pub layer_id: u32,
+    /// True if the Shape Node or any Transform or Group Node above it is hidden (`_hidden`).
+    pub hidden: bool,
+    /// Transform from model space into world space.
+    ///
+    /// Model space has its origin at the centre of the model, so a model of size (x, y, z) spans
+    /// -size/2 to size/2 on each axis before being transformed.
+    pub world_matrix: Matrix,
+}
+
+/// A validated, navigable view of a file's Scene Graph.
+///
+/// Nodes are linked by ID; a `SceneGraph` resolves those links once so the tree can be walked
+/// from the root down or from any node up.
+///
+/// ```text
+///      T
+///      |
+///      G
+///     / \
+///    T   T
+///    |   |
+///    S   S
+/// ```
+#[derive(Clone, Debug)]
+pub struct SceneGraph<'a> {
+    nodes: &'a [SceneNode],
+    index: HashMap<u32, usize>,
+    parents: HashMap<u32, u32>,
+}
+
+impl<'a> SceneGraph<'a> {
+    /// Build a Scene Graph over `nodes`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if node IDs are not unique, a node refers to a child that doesn't exist,
+    /// a node has more than one parent, or the nodes contain a cycle.
+    pub fn new(nodes: &'a [SceneNode]) -> DotVoxResult<SceneGraph<'a>> {
+        SceneGraph::with_locations(nodes, &[])
+    }
+
+    /// Build a Scene Graph over `nodes`, which were read from the chunks at `locations`.
+    ///
+    /// Errors about a node carry the path and offset at the same index in `locations`, if any.
+    pub(crate) fn with_locations(
+        nodes: &'a [SceneNode],
+        locations: &[(String, usize)],
+    ) -> DotVoxResult<SceneGraph<'a>> {
+        let mut index = HashMap::with_capacity(nodes.len());
+        for (position, node) in nodes.iter().enumerate() {
+            if index.insert(node.id(), position).is_some() {
+                let (path, offset) = location(locations, position);
+                return Err(DotVoxError::DuplicateNodeId { id: node.id(), path, offset });
+            }
+        }
+        let mut parents = HashMap::with_capacity(nodes.len());
+        for (position, node) in nodes.iter().enumerate() {
+            for child in child_ids(node) {
+                let Some(child_position) = index.get(child) else {
+                    let (path, offset) = location(locations, position);
+                    return Err(DotVoxError::DanglingNodeReference { node: node.id(), child: *child, path, offset });
+                };
+                if parents.insert(*child, node.id()).is_some() {
+                    let (path, offset) = location(locations, *child_position);
+                    return Err(DotVoxError::MultipleParents { id: *child, path, offset });
+                }
+            }
+        }
+        let graph = SceneGraph { nodes, index, parents };
+        // With at most one parent per node, any node not reachable from a parentless node is on
+        // a cycle.
+        let mut reached = HashSet::with_capacity(nodes.len());
+        for root in nodes.iter().filter(|node| !graph.parents.contains_key(&node.id())) {
+            reached.extend(graph.depth_first_from(root.id()).map(SceneNode::id));
+        }
+        if let Some(position) = nodes.iter().position(|node| !reached.contains(&node.id())) {
+            let (path, offset) = location(locations, position);
+            return Err(DotVoxError::SceneGraphCycle { id: nodes[position].id(), path, offset });
+        }
+        Ok(graph)
+    }
+
+    /// Number of nodes in the graph.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// True if the graph has no nodes.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// The root node of the graph.
+    ///
+    /// This is the first node without a parent, which in files written by `MagicaVoxel` is the
+    /// Transform Node with ID 0.
+    pub fn root(&self) -> Option<&'a SceneNode> {
+        self.nodes.iter().find(|node| !self.parents.contains_key(&node.id()))
+    }
+
+    /// Find the node with `id`.
+    pub fn node(&self, id: u32) -> Option<&'a SceneNode> {
+        self.index.get(&id).map(|position| &self.nodes[*position])
+    }
+
+    /// The direct children of the node with `id`, in order.
+    pub fn children(&self, id: u32) -> impl Iterator<Item = &'a SceneNode> + '_ {
+        self.node(id)
+            .map_or(&[][..], child_ids)
+            .iter()
+            .filter_map(move |child| self.node(*child))
+    }
+
+    /// The parent of the node with `id`, or `None` for root nodes.
+    pub fn parent(&self, id: u32) -> Option<&'a SceneNode> {
+        self.parents.get(&id).and_then(|parent| self.node(*parent))
+    }
+
+    /// Compute the world space placement of every Shape Node in the graph.
+    ///
+    /// Each Transform Node's first frame is composed from the root down, so the world matrix of
+    /// an instance is the product of every transform above it. Instances are returned in depth
+    /// first order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a Transform Node has an invalid frame.
+    pub fn instances(&self) -> DotVoxResult<Vec<ShapeInstance>> {
+        self.instances_at(0)
+    }
+
+    /// Compute the world space placement of every Shape Node at animation `frame`.
+    ///
+    /// See [`TransformNode::transform_at`](crate::TransformNode::transform_at) and
+    /// [`ShapeNode::model_at`](crate::ShapeNode::model_at) for how keyframes are chosen.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a Transform or Shape Node has an invalid keyframe.
+    pub fn instances_at(&self, frame: u32) -> DotVoxResult<Vec<ShapeInstance>> {
+        let mut instances = Vec::new();
+        let mut stack: Vec<(&SceneNode, Matrix, u32, bool)> = self.nodes
+            .iter()
+            .rev()
+            .filter(|node| !self.parents.contains_key(&node.id()))
+            .map(|node| (node, IDENTITY, u32::MAX, false))
+            .collect();
+        while let Some((node, world, layer_id, hidden)) = stack.pop() {
+            let hidden = hidden || node.is_hidden();
+            let (world, layer_id) = match node {
+                SceneNode::Transform(transform) => {
+                    let local = transform.transform_at(frame)?.to_matrix();
+                    (multiply(&world, &local), transform.layer_id)
+                }
+                SceneNode::Group(_) => (world, layer_id),
+                SceneNode::Shape(shape) => {
+                    if let Some(model_id) = shape.model_at(frame)? {
+                        instances.push(ShapeInstance {
+                            node_id: shape.id,
+                            model_id,
+                            layer_id,
+                            hidden,
+                            world_matrix: world,
+                        });
+                    }
+                    continue;
+                }
+            };
+            let first = stack.len();
+            stack.extend(self.children(node.id()).map(|child| (child, world, layer_id, hidden)));
+            stack[first..].reverse();
+        }
+        Ok(instances)
+    }
+
+    /// Walk the graph depth first (pre-order) from the root.
+    pub fn depth_first(&self) -> DepthFirst<'_, 'a> {
+        DepthFirst { graph: self, stack: self.root().into_iter().collect() }
+    }
+
+    /// Walk the subtree under the node with `id` depth first (pre-order), including the node.
+    pub fn depth_first_from(&self, id: u32) -> DepthFirst<'_, 'a> {
+        DepthFirst { graph: self, stack: self.node(id).into_iter().collect() }
+    }
+
+    /// Walk the graph breadth first from the root.
+    pub fn breadth_first(&self) -> BreadthFirst<'_, 'a> {
+        BreadthFirst { graph: self, queue: self.root().into_iter().collect() }
+    }
+
+    /// Walk the subtree under the node with `id` breadth first, including the node.
+    pub fn breadth_first_from(&self, id: u32) -> BreadthFirst<'_, 'a> {
+        BreadthFirst { graph: self, queue: self.node(id).into_iter().collect() }
+    }
+}
+
+/// Depth first iterator over a [`SceneGraph`].
+pub struct DepthFirst<'g, 'a> {
+    graph: &'g SceneGraph<'a>,
+    stack: Vec<&'a SceneNode>,
+}
+
+impl<'a> Iterator for DepthFirst<'_, 'a> {
+    type Item = &'a SceneNode;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        let first = self.stack.len();
+        self.stack.extend(self.graph.children(node.id()));
+        self.stack[first..].reverse();
+        Some(node)
+    }
+}
+
+/// Breadth first iterator over a [`SceneGraph`].
+pub struct BreadthFirst<'g, 'a> {
+    graph: &'g SceneGraph<'a>,
+    queue: VecDeque<&'a SceneNode>,
+}
+
+impl<'a> Iterator for BreadthFirst<'_, 'a> {
+    type Item = &'a SceneNode;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.queue.pop_front()?;
+        self.queue.extend(self.graph.children(node.id()));
+        Some(node)
+    }
+}
+
+impl VoxFile {
+    /// Build a validated [`SceneGraph`] over this file's scene nodes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the scene graph is malformed or a Shape Node refers to a model that
+    /// doesn't exist.
+    pub fn scene_graph(&self) -> DotVoxResult<SceneGraph<'_>> {
+        let graph = SceneGraph::with_locations(&self.scenegraph, &self.scene_locations)?;
+        for (position, node) in self.scenegraph.iter().enumerate() {
+            if let SceneNode::Shape(shape) = node {
+                for (model, _) in &shape.models {
+                    if *model as usize >= self.models.len() {
+                        let (path, offset) = location(&self.scene_locations, position);
+                        return Err(DotVoxError::DanglingModelReference { node: shape.id, model: *model, path, offset });
+                    }
+                }
+            }
+        }
+        Ok(graph)
+    }
+
+    /// Compute the world space placement of every model in this file.
+    ///
+    /// Files without a scene graph place each model once, untransformed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the scene graph is malformed.
+    pub fn instances(&self) -> DotVoxResult<Vec<ShapeInstance>> {
+        self.instances_at(0)
+    }
+
+    /// Compute the world space placement of every model in this file at animation `frame`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the scene graph is malformed.
+    pub fn instances_at(&self, frame: u32) -> DotVoxResult<Vec<ShapeInstance>> {
+        if self.scenegraph.is_empty() {
+            return Ok((0..self.models.len())
+                .map(|model_id| ShapeInstance {
+                    node_id: 0,
+                    model_id: u32::try_from(model_id).unwrap_or(u32::MAX),
+                    layer_id: 0,
+                    hidden: false,
+                    world_matrix: IDENTITY,
+                })
+                .collect());
+        }
+        self.scene_graph()?.instances_at(frame)
+    }
+}
+
+/// Multiply two affine matrices.
+fn multiply(lhs: &Matrix, rhs: &Matrix) -> Matrix {
+    let mut result = [[0.0; 4]; 4];
+    for (row, values) in result.iter_mut().enumerate() {
+        for (column, value) in values.iter_mut().enumerate() {
+            *value = (0..4).map(|i| lhs[row][i] * rhs[i][column]).sum();
+        }
+    }
+    result
+}
+
+/// IDs of the children of `node`.
+pub(crate) fn child_ids(node: &SceneNode) -> &[u32] {
+    match node {
+        SceneNode::Transform(node) => std::slice::from_ref(&node.child_node_id),
+        SceneNode::Group(node) => &node.children,
+        SceneNode::Shape(_) => &[],
+    }
+}
+
+/// The chunk path and offset of the node at `position`, if it was read from a file.
+fn location(locations: &[(String, usize)], position: usize) -> (Option<String>, Option<usize>) {
+    match locations.get(position) {
+        Some((path, offset)) => (Some(path.clone()), Some(*offset)),
+        None => (None, None),
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::float_cmp)]
+mod tests {
+    use super::SceneGraph;
+    use crate::{DotVoxError, Dict, GroupNode, SceneNode, ShapeNode, TransformNode, VoxFile};
+
+    fn transform(id: u32, child_node_id: u32) -> SceneNode {
+        SceneNode::Transform(TransformNode {
+            id,
+            attrib: Dict::new(),
+            child_node_id,
+            reserved_id: -1,
+            layer_id: 0,
+            frames: vec![Dict::new()],
+        })
+    }
+
+    fn group(id: u32, children: Vec<u32>) -> SceneNode {
+        SceneNode::Group(GroupNode { id, attrib: Dict::new(), children })
+    }
+
+    fn shape(id: u32) -> SceneNode {
+        SceneNode::Shape(ShapeNode { id, attrib: Dict::new(), models: vec![(0, Dict::new())] })
+    }
+
+    #[test]
+    fn test_streetlamp() {
+        let file = VoxFile::open("vox/streetlamp.vox").expect("Error loading test file.");
+        let graph = file.scene_graph().expect("Invalid scene graph.");
+        assert_eq!(graph.root().map(SceneNode::id), Some(0));
+        let children: Vec<u32> = graph.children(1).map(SceneNode::id).collect();
+        assert_eq!(children, vec![4, 2]);
+        assert_eq!(graph.parent(5).map(SceneNode::id), Some(4));
+        assert!(graph.parent(0).is_none());
+        let depth_first: Vec<u32> = graph.depth_first().map(SceneNode::id).collect();
+        assert_eq!(depth_first, vec![0, 1, 4, 5, 2, 3]);
+        let breadth_first: Vec<u32> = graph.breadth_first().map(SceneNode::id).collect();
+        assert_eq!(breadth_first, vec![0, 1, 4, 2, 5, 3]);
+    }
+
+    #[test]
+    fn test_instances() {
+        let file = VoxFile::open("vox/streetlamp.vox").expect("Error loading test file.");
+        let instances = file.instances().expect("Invalid scene graph.");
+        assert_eq!(instances.len(), 2);
+        assert_eq!(instances[0].model_id, 1);
+        assert_eq!(instances[0].layer_id, 0);
+        assert_eq!(instances[0].world_matrix[2][3], 128.0);
+        assert_eq!(instances[1].model_id, 0);
+        let translation: Vec<f32> = instances[1].world_matrix.iter().map(|row| row[3]).collect();
+        assert_eq!(translation, vec![-25.0, 0.0, 20.0, 1.0]);
+
+        let file = VoxFile::open("vox/T-Rex.vox").expect("Error loading test file.");
+        assert_eq!(file.instances().expect("Invalid scene graph.").len(), 8);
+    }
+
+    #[test]
+    fn test_nested_instances() {
+        let mut root = transform(0, 1);
+        let mut inner = transform(2, 3);
+        if let (SceneNode::Transform(root), SceneNode::Transform(inner)) = (&mut root, &mut inner) {
+            // Rotate 90 degrees about Z, then translate.
+            root.frames[0].insert("_r", "17");
+            root.frames[0].insert("_t", "10 0 0");
+            inner.frames[0].insert("_t", "1 2 3");
+            inner.layer_id = 2;
+        }
+        let nodes = [root, group(1, vec![2]), inner, shape(3)];
+        let graph = SceneGraph::new(&nodes).expect("Invalid scene graph.");
+        let instances = graph.instances().expect("Invalid transforms.");
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances[0].layer_id, 2);
+        assert_eq!(instances[0].world_matrix, [
+            [0.0, -1.0, 0.0, 8.0],
+            [1.0, 0.0, 0.0, 1.0],
+            [0.0, 0.0, 1.0, 3.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+    }
+
+    #[test]
+    fn test_invalid_transform() {
+        let mut root = transform(0, 1);
+        if let SceneNode::Transform(root) = &mut root {
+            root.frames[0].insert("_t", "1 2");
+        }
+        let nodes = [root, shape(1)];
+        let graph = SceneGraph::new(&nodes).expect("Invalid scene graph.");
+        assert!(matches!(graph.instances(), Err(DotVoxError::InvalidAttribute { .. })));
+    }
+
+    #[test]
+    fn test_all_samples_valid() {
+        for entry in std::fs::read_dir("vox").expect("Error listing test files.") {
+            let path = entry.expect("Error listing test files.").path();
+            let file = VoxFile::open(&path).expect("Error loading test file.");
+            let graph = file.scene_graph().expect("Invalid scene graph.");
+            assert_eq!(graph.depth_first().count(), file.scenegraph.len());
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        let dangling = [transform(0, 1), group(1, vec![2, 4]), transform(2, 3), shape(3)];
+        match SceneGraph::new(&dangling) {
+            Err(DotVoxError::DanglingNodeReference { node: 1, child: 4, .. }) => {}
+            other => panic!("Unexpected result: {:?}", other.map(|graph| graph.len())),
+        }
+        let cycle = [transform(0, 1), shape(1), transform(2, 3), group(3, vec![2])];
+        match SceneGraph::new(&cycle) {
+            Err(DotVoxError::SceneGraphCycle { id: 2, .. }) => {}
+            other => panic!("Unexpected result: {:?}", other.map(|graph| graph.len())),
+        }
+        let shared = [transform(0, 1), group(1, vec![2, 2]), shape(2)];
+        match SceneGraph::new(&shared) {
+            Err(DotVoxError::MultipleParents { id: 2, .. }) => {}
+            other => panic!("Unexpected result: {:?}", other.map(|graph| graph.len())),
+        }
+        let duplicate = [transform(0, 1), shape(1), shape(1)];
+        match SceneGraph::new(&duplicate) {
+            Err(DotVoxError::DuplicateNodeId { id: 1, .. }) => {}
+            other => panic!("Unexpected result: {:?}", other.map(|graph| graph.len())),
+        }
+    }
+
+    #[test]
+    fn test_invalid_location() {
+        let bytes = std::fs::read("vox/3x3x3.vox").expect("Error opening test file.");
+        let mut file = VoxFile::from_bytes(&bytes).expect("Error loading test file.");
+        for node in &mut file.scenegraph {
+            if let SceneNode::Group(node) = node {
+                node.children.push(1000);
+            }
+        }
+        let err = file.scene_graph().map(|graph| graph.len()).expect_err("Dangling child");
+        assert!(matches!(err, DotVoxError::DanglingNodeReference { child: 1000, .. }));
+        assert_eq!(err.path(), Some("MAIN/nGRP[0]"));
+        let offset = err.offset().expect("Error offset");
+        assert_eq!(&bytes[offset..offset + 4], b"nGRP");
+    }
+}
@@ -14,16 +14,89 @@
 // limitations under the License.
 //
 
-use std::collections::HashMap;
-use nom::lib::std::fmt::Formatter;
+use std::iter::FromIterator;
+use crate::error::{DotVoxError, DotVoxResult, ReadWarning};
+use crate::scene::{Matrix, IDENTITY};
 
-const MAGIC_NUMBER: &'static str = "VOX ";
+/// RIFF type of a .vox file.
+pub(crate) const MAGIC_NUMBER: &str = "VOX ";
 
 /// (String, String) Dictionary
-pub type Dict = HashMap<String, String>;
+///
+/// Entries are kept in insertion order so a dictionary is written back exactly as it was read.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Dict(Vec<(String, String)>);
+
+impl Dict {
+    /// Create an empty Dictionary.
+    pub fn new() -> Dict {
+        Dict(Vec::new())
+    }
+
+    /// Create a Dictionary holding `entries` as they are, including any repeated keys, so it is
+    /// written back exactly as it was read.
+    pub(crate) fn from_entries(entries: Vec<(String, String)>) -> Dict {
+        Dict(entries)
+    }
+
+    /// Number of entries in the Dictionary.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// True if the Dictionary has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Get the value stored under `key`, the first one if a file repeats the key.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    /// True if a value is stored under `key`.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Store `value` under `key`, returning the previous value.
+    ///
+    /// Replacing a value keeps the entry's position; new keys are appended.
+    pub fn insert<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) -> Option<String> {
+        let key = key.into();
+        let value = value.into();
+        if let Some((_, existing)) = self.0.iter_mut().find(|(k, _)| *k == key) {
+            Some(std::mem::replace(existing, value))
+        } else {
+            self.0.push((key, value));
+            None
+        }
+    }
+
+    /// Remove and return the value stored under `key`.
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let index = self.0.iter().position(|(k, _)| k == key)?;
+        Some(self.0.remove(index).1)
+    }
+
+    /// Iterate over the entries in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
+
+impl FromIterator<(String, String)> for Dict {
+    fn from_iter<T: IntoIterator<Item = (String, String)>>(iter: T) -> Self {
+        let mut dict = Dict::new();
+        for (key, value) in iter {
+            dict.insert(key, value);
+        }
+        dict
+    }
+}
 
 /// RGBA 32 bit color
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Color {
     pub name: Option<String>,
     pub r: u8,
@@ -33,19 +106,35 @@ pub struct Color {
 }
 
 impl Color {
+    /// Color from a `0xRRGGBBAA` value.
     pub const fn from_u32(val: u32) -> Self {
         Color {
             name: None,
-            r: ((val & 0xFF000000) >> 24) as u8,
-            g: ((val & 0x00FF0000) >> 16) as u8,
-            b: ((val & 0x0000FF00) >> 08) as u8,
-            a: ((val & 0x000000FF) >> 00) as u8,
+            r: ((val & 0xFF00_0000) >> 24) as u8,
+            g: ((val & 0x00FF_0000) >> 16) as u8,
+            b: ((val & 0x0000_FF00) >> 8) as u8,
+            a: (val & 0x0000_00FF) as u8,
+        }
+    }
+
+    /// Color from a `0xAABBGGRR` value, the layout of the default palette in the spec and of
+    /// colors in the file read as little endian `u32`s.
+    pub const fn from_abgr(val: u32) -> Self {
+        Color {
+            name: None,
+            r: (val & 0x0000_00FF) as u8,
+            g: ((val & 0x0000_FF00) >> 8) as u8,
+            b: ((val & 0x00FF_0000) >> 16) as u8,
+            a: ((val & 0xFF00_0000) >> 24) as u8,
         }
     }
+
+    /// This color as a `0xAABBGGRR` value, see [`Color::from_abgr`].
+    pub const fn to_abgr(&self) -> u32 {
+        (self.r as u32) | ((self.g as u32) << 8) | ((self.b as u32) << 16) | ((self.a as u32) << 24)
+    }
 }
 
-/// TODO: Figure out how to parse this.
-///
 /// (c) ROTATION type
 ///
 /// store a row-major rotation in the bits of a byte
@@ -65,47 +154,122 @@ impl Color {
 /// 5   : 1 : the sign in the second row (0 : positive; 1 : negative)
 /// 6   : 1 : the sign in the third row (0 : positive; 1 : negative)
 ///
-#[derive(Clone, Debug)]
+/// Every valid byte is one of the 48 signed permutation matrices, which covers all 24 rotations
+/// aligned to the voxel grid and their mirror images.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Rotation(pub u8);
 
 impl Rotation {
-    /*
-    pub fn from_matrix(matrix: [[f32; 3]; 3]) -> Rotation {
+    /// The Rotation that leaves everything in place.
+    pub const fn identity() -> Rotation {
+        Rotation(1 << 2)
+    }
+
+    /// Encode a signed permutation matrix.
+    ///
+    /// Returns `None` unless every row and column has exactly one non-zero entry of 1 or -1.
+    #[allow(clippy::float_cmp, clippy::cast_possible_truncation)]
+    pub fn from_matrix(matrix: [[f32; 3]; 3]) -> Option<Rotation> {
         let mut result = 0u8;
-        //which idx of row has the +/- 1
-        let idx0 = 0;
-        let idx1 = 0;
-        let idx2 = 0;
+        let mut columns = [false; 3];
+        for (row, values) in matrix.iter().enumerate() {
+            let mut non_zero = values.iter().enumerate().filter(|(_, value)| **value != 0.0);
+            let (column, value) = non_zero.next()?;
+            if non_zero.next().is_some() || value.abs() != 1.0 || columns[column] {
+                return None;
+            }
+            columns[column] = true;
+            if row < 2 {
+                result |= (column as u8) << (row * 2);
+            }
+            if *value < 0.0 {
+                result |= 1 << (4 + row);
+            }
+        }
+        Some(Rotation(result))
+    }
 
-        /* Get idx0,1,2 correct here */
+    /// Decode this Rotation into a row-major matrix.
+    ///
+    /// The result is only meaningful if the Rotation [is valid](Rotation::is_valid).
+    pub fn to_matrix(&self) -> [[f32; 3]; 3] {
+        let mut result = [[0.0; 3]; 3];
+        for (row, (column, sign)) in self.entries().iter().enumerate() {
+            result[row][*column] = f32::from(*sign);
+        }
+        result
+    }
 
-        result |= (idx0 << 0);
-        result |= (idx1 << 2);
-        result |= ((matrix[0][idx0 as usize] == -1.0) << 4);
-        result |= ((matrix[1][idx1 as usize] == -1.0) << 5);
-        result |= ((matrix[2][idx2 as usize] == -1.0) << 6);
+    /// True if the byte encodes a signed permutation matrix.
+    pub fn is_valid(&self) -> bool {
+        let (idx0, idx1) = (self.0 & 0x03, (self.0 >> 2) & 0x03);
+        idx0 < 3 && idx1 < 3 && idx0 != idx1 && self.0 & 0x80 == 0
+    }
 
-        Rotation(result)
+    /// The Rotation that undoes this one.
+    #[must_use]
+    pub fn inverse(&self) -> Rotation {
+        let mut transpose = [[0.0; 3]; 3];
+        for (row, (column, sign)) in self.entries().iter().enumerate() {
+            transpose[*column][row] = f32::from(*sign);
+        }
+        Rotation::from_matrix(transpose).unwrap_or_else(Rotation::identity)
     }
-     */
-    pub fn to_matrix(&self) -> [[f32; 3]; 3] {
-        let mut result = [[0.0; 3]; 3];
-        let (_01, _23, _4, _5, _6) = (
-            0x03 & self.0 >> 0,
-            0x0C & self.0 >> 2,
-            0x10 & self.0 >> 3,
-            0x20 & self.0 >> 4,
-            0x40 & self.0 >> 5,
-        );
-        let idx0 = _01;
-        let idx1 = _23;
-        let idx2 = 3 - idx0 - idx1;
-
-        result[0][idx0 as usize] = 1.0 - _4 as f32 * 2.0;
-        result[1][idx1 as usize] = 1.0 - _5 as f32 * 2.0;
-        result[2][idx2 as usize] = 1.0 - _6 as f32 * 2.0;
+
+    /// Rotate an integer voxel coordinate.
+    pub fn apply(&self, point: [i32; 3]) -> [i32; 3] {
+        let mut result = [0; 3];
+        for (row, (column, sign)) in self.entries().iter().enumerate() {
+            result[row] = point[*column] * i32::from(*sign);
+        }
         result
     }
+
+    /// Rotate the dimensions of a model, giving the size of its rotated bounding box.
+    pub fn apply_size(&self, size: Size) -> Size {
+        let dimensions = [size.x, size.y, size.z];
+        let entries = self.entries();
+        Size {
+            x: dimensions[entries[0].0],
+            y: dimensions[entries[1].0],
+            z: dimensions[entries[2].0],
+        }
+    }
+
+    /// The column and sign of the non-zero entry of each row.
+    fn entries(self) -> [(usize, i8); 3] {
+        let idx0 = self.0 & 0x03;
+        let idx1 = (self.0 >> 2) & 0x03;
+        let idx2 = 3u8.wrapping_sub(idx0).wrapping_sub(idx1);
+        let sign = |bit: u8| if self.0 & (1 << bit) == 0 { 1 } else { -1 };
+        [
+            (usize::from(idx0.min(2)), sign(4)),
+            (usize::from(idx1.min(2)), sign(5)),
+            (usize::from(idx2.min(2)), sign(6)),
+        ]
+    }
+}
+
+impl Default for Rotation {
+    fn default() -> Rotation {
+        Rotation::identity()
+    }
+}
+
+impl std::ops::Mul for Rotation {
+    type Output = Rotation;
+
+    /// Compose two Rotations; the result applies `rhs` first, then `self`.
+    fn mul(self, rhs: Rotation) -> Rotation {
+        let lhs = self.entries();
+        let rhs = rhs.entries();
+        let mut result = [[0.0; 3]; 3];
+        for (row, (middle, lhs_sign)) in lhs.iter().enumerate() {
+            let (column, rhs_sign) = rhs[*middle];
+            result[row][column] = f32::from(lhs_sign * rhs_sign);
+        }
+        Rotation::from_matrix(result).unwrap_or_else(Rotation::identity)
+    }
 }
 
 /// Container for .vox file data
@@ -115,15 +279,70 @@ pub struct VoxFile {
     pub version: u32,
     /// A Vec of all models contained in this file.
     pub models: Vec<Model>,
-    /// A Vec containing the color palette.
+    /// The color palette, indexed by [`Voxel::i`].
+    ///
+    /// Index 0 is unused by voxels. The RGBA chunk stores the colors of indices 1-255 followed by
+    /// index 0, they are shifted into place when reading and back when writing.
     pub palette: [Color; 256],
     /// A Vec containing all the Materials in this file.
     pub materials: Vec<Material>,
-    /// A Scene Graph
+    /// A Scene Graph, with nodes in the order they appear in the file.
     pub scenegraph: Vec<SceneNode>,
+    /// A Vec of all scene Layers in this file.
+    pub layers: Vec<Layer>,
+    /// A Vec of all render Cameras in this file.
+    pub cameras: Vec<Camera>,
+    /// A Vec of all render object settings (rOBJ) in this file.
+    pub render_objects: Vec<Dict>,
+    /// Palette notes (NOTE), the names of the rows of the palette editor.
+    ///
+    /// See [`VoxFile::palette_groups`].
+    pub notes: Vec<String>,
+    /// Palette index map (IMAP), the palette indices in the order `MagicaVoxel` shows them.
+    ///
+    /// See [`VoxFile::display_order`].
+    pub index_map: Option<[u8; 256]>,
+    /// Whether a PACK chunk holding the model count is written before the models.
+    ///
+    /// Set when reading files that contain one.
+    pub pack: bool,
+    /// Chunks this crate doesn't understand, each with its index among the MAIN chunk's children
+    /// so they are written back where they were found.
+    pub unknown_chunks: Vec<(usize, Chunk)>,
+    /// The ids of the MAIN chunk's other children in the order they were read, so they are
+    /// written back in that order. Empty for files built in memory, which are written in the
+    /// order `MagicaVoxel` uses.
+    pub chunk_order: Vec<String>,
+    /// The chunk path and byte offset each node of `scenegraph` was read from, by index, so
+    /// errors in the scene graph can say where the node is in the file.
+    pub scene_locations: Vec<(String, usize)>,
+    /// Problems recovered from while reading this file leniently.
+    ///
+    /// See [`ReadOptions::lenient`](crate::ReadOptions::lenient).
+    pub warnings: Vec<ReadWarning>,
 }
 
-impl VoxFile {}
+impl VoxFile {
+    /// Find the Scene Node with `id`.
+    pub fn scene_node(&self, id: u32) -> Option<&SceneNode> {
+        self.scenegraph.iter().find(|node| node.id() == id)
+    }
+
+    /// Find the Layer with `id`.
+    pub fn layer(&self, id: u32) -> Option<&Layer> {
+        self.layers.iter().find(|layer| layer.id == id)
+    }
+
+    /// The color of `voxel`.
+    pub fn color_of(&self, voxel: &Voxel) -> &Color {
+        &self.palette[usize::from(voxel.i)]
+    }
+
+    /// Find the Camera with `id`.
+    pub fn camera(&self, id: u32) -> Option<&Camera> {
+        self.cameras.iter().find(|camera| camera.id == id)
+    }
+}
 
 impl Default for VoxFile {
     fn default() -> VoxFile {
@@ -133,11 +352,22 @@ impl Default for VoxFile {
             palette: DEFAULT_PALETTE.clone(),
             materials: Vec::new(),
             scenegraph: Vec::new(),
+            layers: Vec::new(),
+            cameras: Vec::new(),
+            render_objects: Vec::new(),
+            notes: Vec::new(),
+            index_map: None,
+            pack: false,
+            unknown_chunks: Vec::new(),
+            chunk_order: Vec::new(),
+            scene_locations: Vec::new(),
+            warnings: Vec::new(),
         }
     }
 }
 
-#[derive(Debug)]
+/// A chunk of a .vox file, as read by [`read_chunks`](crate::read_chunks).
+#[derive(Clone, Debug, PartialEq)]
 #[allow(non_camel_case_types)]
 pub enum Chunk {
     /// Main Chunk, No Content, Many Children
@@ -148,7 +378,7 @@ pub enum Chunk {
     XYZI(Vec<Voxel>),
     /// Pack Chunk
     PACK(Pack),
-    /// Color Palette
+    /// Color Palette, in file order (the colors of palette indices 1-255, then 0).
     RGBA(Vec<Color>),
     /// V1 Material
     MATT(MaterialV1),
@@ -178,13 +408,45 @@ pub enum Chunk {
     },
 }
 
-#[derive(Clone, Debug)]
+impl Chunk {
+    /// The four character id of the chunk.
+    pub fn id(&self) -> &str {
+        match self {
+            Chunk::MAIN(_) => "MAIN",
+            Chunk::SIZE(_) => "SIZE",
+            Chunk::XYZI(_) => "XYZI",
+            Chunk::PACK(_) => "PACK",
+            Chunk::RGBA(_) => "RGBA",
+            Chunk::MATT(_) => "MATT",
+            Chunk::MATL(_) => "MATL",
+            Chunk::rOBJ(_) => "rOBJ",
+            Chunk::rCAM(_) => "rCAM",
+            Chunk::IMAP(_) => "IMAP",
+            Chunk::NOTE(_) => "NOTE",
+            Chunk::nTRN(_) => "nTRN",
+            Chunk::nGRP(_) => "nGRP",
+            Chunk::nSHP(_) => "nSHP",
+            Chunk::LAYR(_) => "LAYR",
+            Chunk::Unknown { kind, .. } => kind,
+        }
+    }
+
+    /// The child chunks of the chunk.
+    pub fn children(&self) -> &[Chunk] {
+        match self {
+            Chunk::MAIN(children) | Chunk::Unknown { children, .. } => children,
+            _ => &[],
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Pack(pub u32);
 
 /// A Sparse Volumetric Pixel Model.
 ///
 /// Sparse Voxel Models store each voxel as an (x,y,z) point in space and a palette index.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Model {
     pub id: u32,
     /// The size of the model in voxels.
@@ -205,7 +467,7 @@ pub struct Size {
 }
 
 /// A sparse volumetric pixel.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Voxel {
     /// The X coordinate of the voxel.
     pub x: u8,
@@ -213,10 +475,9 @@ pub struct Voxel {
     pub y: u8,
     /// The Z coordinate of the voxel.
     pub z: u8,
-    /// Index in the Color Palette.
+    /// Index in the Color Palette, exactly as stored in the file.
     ///
-    /// Note: This value will be 1 less than the value stored in the file. The .vox file format uses
-    /// index ranges from 1-255 where memory array indexing is from 0-254.
+    /// Voxels use indices 1-255, and their color is `palette[i]`, see [`VoxFile::color_of`].
     pub i: u8,
 }
 
@@ -272,14 +533,39 @@ pub struct MaterialV2 {
     pub properties: Dict,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum SceneNode {
     Transform(TransformNode),
     Group(GroupNode),
     Shape(ShapeNode),
 }
 
-#[derive(Clone, Debug)]
+impl SceneNode {
+    /// The Node's ID
+    pub fn id(&self) -> u32 {
+        match self {
+            SceneNode::Transform(node) => node.id,
+            SceneNode::Group(node) => node.id,
+            SceneNode::Shape(node) => node.id,
+        }
+    }
+
+    /// The Node's attributes.
+    pub fn attributes(&self) -> &Dict {
+        match self {
+            SceneNode::Transform(node) => &node.attrib,
+            SceneNode::Group(node) => &node.attrib,
+            SceneNode::Shape(node) => &node.attrib,
+        }
+    }
+
+    /// True if the Node is hidden (`_hidden`).
+    pub fn is_hidden(&self) -> bool {
+        self.attributes().get("_hidden") == Some("1")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct TransformNode {
     pub id: u32,
     pub attrib: Dict,
@@ -289,98 +575,450 @@ pub struct TransformNode {
     pub frames: Vec<Dict>,
 }
 
-#[derive(Clone, Debug)]
+impl TransformNode {
+    /// The Node's name (`_name`), if it has one.
+    pub fn name(&self) -> Option<&str> {
+        self.attrib.get("_name")
+    }
+
+    /// True if the Node is hidden (`_hidden`).
+    pub fn is_hidden(&self) -> bool {
+        self.attrib.get("_hidden") == Some("1")
+    }
+
+    /// Parse every frame of this Node.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a frame has an invalid attribute.
+    pub fn keyframes(&self) -> DotVoxResult<Vec<Frame>> {
+        self.frames.iter().map(Frame::from_dict).collect()
+    }
+
+    /// Replace the frames of this Node.
+    ///
+    /// Attributes other than `_r`, `_t` and `_f` already present on existing frames are kept.
+    pub fn set_keyframes(&mut self, keyframes: &[Frame]) {
+        self.frames.resize_with(keyframes.len(), Dict::new);
+        for (dict, keyframe) in self.frames.iter_mut().zip(keyframes) {
+            keyframe.write_dict(dict);
+        }
+    }
+
+    /// The transform in effect at animation `frame`.
+    ///
+    /// Keyframes hold until the next one starts. Frames before the first keyframe use the first
+    /// keyframe and a Node without frames is untransformed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a frame has an invalid attribute.
+    pub fn transform_at(&self, frame: u32) -> DotVoxResult<Frame> {
+        let mut keyframes = self.keyframes()?;
+        keyframes.sort_by_key(|keyframe| keyframe.frame_index);
+        Ok(keyframes
+            .iter()
+            .rev()
+            .find(|keyframe| keyframe.frame_index <= frame)
+            .or_else(|| keyframes.first())
+            .copied()
+            .unwrap_or_default())
+    }
+}
+
+/// A keyframe of a Transform Node.
+///
+/// Stored in the file as a DICT with the optional attributes
+///
+/// | Attribute | Description
+/// |-----------|--------------------------------------
+/// | _r        | [Rotation] as a decimal byte
+/// | _t        | Translation, 'X Y Z' as integers
+/// | _f        | Animation frame the keyframe starts at
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Frame {
+    /// Rotation about the centre of the Node's contents.
+    pub rotation: Rotation,
+    /// Translation in voxels.
+    pub translation: [i32; 3],
+    /// Animation frame this keyframe starts at.
+    pub frame_index: u32,
+}
+
+impl Frame {
+    /// Parse a Frame from its DICT. Missing attributes take their default values.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an attribute has a value that can't be parsed.
+    pub fn from_dict(dict: &Dict) -> DotVoxResult<Frame> {
+        let invalid = |key: &str, value: &str| DotVoxError::InvalidAttribute {
+            key: key.to_owned(),
+            value: value.to_owned(),
+        };
+        let mut frame = Frame::default();
+        if let Some(value) = dict.get("_r") {
+            frame.rotation = Rotation(value.trim().parse().map_err(|_| invalid("_r", value))?);
+            if !frame.rotation.is_valid() {
+                return Err(invalid("_r", value));
+            }
+        }
+        if let Some(value) = dict.get("_t") {
+            let mut offsets = value.split_whitespace().map(str::parse::<i32>);
+            for axis in &mut frame.translation {
+                match offsets.next() {
+                    Some(Ok(offset)) => *axis = offset,
+                    _ => return Err(invalid("_t", value)),
+                }
+            }
+            if offsets.next().is_some() {
+                return Err(invalid("_t", value));
+            }
+        }
+        if let Some(value) = dict.get("_f") {
+            frame.frame_index = value.trim().parse().map_err(|_| invalid("_f", value))?;
+        }
+        Ok(frame)
+    }
+
+    /// Build the DICT for this Frame, omitting attributes with default values.
+    pub fn to_dict(&self) -> Dict {
+        let mut dict = Dict::new();
+        self.write_dict(&mut dict);
+        dict
+    }
+
+    /// Store this Frame's attributes in `dict`, leaving any other attributes untouched.
+    pub fn write_dict(&self, dict: &mut Dict) {
+        if self.rotation == Rotation::identity() {
+            dict.remove("_r");
+        } else {
+            dict.insert("_r", self.rotation.0.to_string());
+        }
+        if self.translation == [0; 3] {
+            dict.remove("_t");
+        } else {
+            let [x, y, z] = self.translation;
+            dict.insert("_t", format!("{x} {y} {z}"));
+        }
+        if self.frame_index == 0 {
+            dict.remove("_f");
+        } else {
+            dict.insert("_f", self.frame_index.to_string());
+        }
+    }
+
+    /// This Frame as an affine transformation matrix.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn to_matrix(&self) -> Matrix {
+        let mut matrix = IDENTITY;
+        for (row, values) in self.rotation.to_matrix().iter().enumerate() {
+            matrix[row][..3].copy_from_slice(values);
+            matrix[row][3] = self.translation[row] as f32;
+        }
+        matrix
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct GroupNode {
     pub id: u32,
     pub attrib: Dict,
     pub children: Vec<u32>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct ShapeNode {
     pub id: u32,
     pub attrib: Dict,
+    /// Models shown by this Node and their attributes. Animated Nodes hold one model per keyframe.
     pub models: Vec<(u32, Dict)>,
 }
 
+impl ShapeNode {
+    /// Parse the model keyframes of this Node.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a model has an invalid frame index (`_f`).
+    pub fn keyframes(&self) -> DotVoxResult<Vec<ShapeKeyframe>> {
+        self.models
+            .iter()
+            .map(|(model_id, attributes)| {
+                let frame_index = match attributes.get("_f") {
+                    Some(value) => value.trim().parse().map_err(|_| DotVoxError::InvalidAttribute {
+                        key: String::from("_f"),
+                        value: value.to_owned(),
+                    })?,
+                    None => 0,
+                };
+                Ok(ShapeKeyframe { model_id: *model_id, frame_index })
+            })
+            .collect()
+    }
+
+    /// Replace the model keyframes of this Node.
+    ///
+    /// Attributes other than `_f` already present on existing models are kept.
+    pub fn set_keyframes(&mut self, keyframes: &[ShapeKeyframe]) {
+        self.models.resize_with(keyframes.len(), || (0, Dict::new()));
+        for ((model_id, attributes), keyframe) in self.models.iter_mut().zip(keyframes) {
+            *model_id = keyframe.model_id;
+            if keyframe.frame_index == 0 {
+                attributes.remove("_f");
+            } else {
+                attributes.insert("_f", keyframe.frame_index.to_string());
+            }
+        }
+    }
+
+    /// The model shown at animation `frame`, or `None` if the Node has no models.
+    ///
+    /// Keyframes hold until the next one starts and frames before the first keyframe show the
+    /// first keyframe's model.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a model has an invalid frame index (`_f`).
+    pub fn model_at(&self, frame: u32) -> DotVoxResult<Option<u32>> {
+        let mut keyframes = self.keyframes()?;
+        keyframes.sort_by_key(|keyframe| keyframe.frame_index);
+        Ok(keyframes
+            .iter()
+            .rev()
+            .find(|keyframe| keyframe.frame_index <= frame)
+            .or_else(|| keyframes.first())
+            .map(|keyframe| keyframe.model_id))
+    }
+}
+
+/// A model shown by a Shape Node from an animation frame on.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ShapeKeyframe {
+    /// ID of the model shown.
+    pub model_id: u32,
+    /// Animation frame this keyframe starts at (`_f`).
+    pub frame_index: u32,
+}
+
 /// (5) Layer Chunk
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Layer {
     pub id: u32,
-    pub attributes: HashMap<String, String>,
+    pub attributes: Dict,
     pub reserved: i32,
 }
 
+impl Layer {
+    /// The Layer's name (`_name`), if it has one.
+    pub fn name(&self) -> Option<&str> {
+        self.attributes.get("_name")
+    }
+
+    /// True if the Layer is hidden (`_hidden`).
+    pub fn is_hidden(&self) -> bool {
+        self.attributes.get("_hidden") == Some("1")
+    }
+}
+
 /// Camera
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Camera {
     pub id: u32,
-    pub attributes: HashMap<String,String>,
-}
-
-const DEFAULT_PALETTE: [Color; 256] = [
-    Color::from_u32(0x00000000), Color::from_u32(0xffffffff), Color::from_u32(0xffccffff), Color::from_u32(0xff99ffff),
-    Color::from_u32(0xff66ffff), Color::from_u32(0xff33ffff), Color::from_u32(0xff00ffff), Color::from_u32(0xffffccff),
-    Color::from_u32(0xffccccff), Color::from_u32(0xff99ccff), Color::from_u32(0xff66ccff), Color::from_u32(0xff33ccff),
-    Color::from_u32(0xff00ccff), Color::from_u32(0xffff99ff), Color::from_u32(0xffcc99ff), Color::from_u32(0xff9999ff),
-    Color::from_u32(0xff6699ff), Color::from_u32(0xff3399ff), Color::from_u32(0xff0099ff), Color::from_u32(0xffff66ff),
-    Color::from_u32(0xffcc66ff), Color::from_u32(0xff9966ff), Color::from_u32(0xff6666ff), Color::from_u32(0xff3366ff),
-    Color::from_u32(0xff0066ff), Color::from_u32(0xffff33ff), Color::from_u32(0xffcc33ff), Color::from_u32(0xff9933ff),
-    Color::from_u32(0xff6633ff), Color::from_u32(0xff3333ff), Color::from_u32(0xff0033ff), Color::from_u32(0xffff00ff),
-    Color::from_u32(0xffcc00ff), Color::from_u32(0xff9900ff), Color::from_u32(0xff6600ff), Color::from_u32(0xff3300ff),
-    Color::from_u32(0xff0000ff), Color::from_u32(0xffffffcc), Color::from_u32(0xffccffcc), Color::from_u32(0xff99ffcc),
-    Color::from_u32(0xff66ffcc), Color::from_u32(0xff33ffcc), Color::from_u32(0xff00ffcc), Color::from_u32(0xffffcccc),
-    Color::from_u32(0xffcccccc), Color::from_u32(0xff99cccc), Color::from_u32(0xff66cccc), Color::from_u32(0xff33cccc),
-    Color::from_u32(0xff00cccc), Color::from_u32(0xffff99cc), Color::from_u32(0xffcc99cc), Color::from_u32(0xff9999cc),
-    Color::from_u32(0xff6699cc), Color::from_u32(0xff3399cc), Color::from_u32(0xff0099cc), Color::from_u32(0xffff66cc),
-    Color::from_u32(0xffcc66cc), Color::from_u32(0xff9966cc), Color::from_u32(0xff6666cc), Color::from_u32(0xff3366cc),
-    Color::from_u32(0xff0066cc), Color::from_u32(0xffff33cc), Color::from_u32(0xffcc33cc), Color::from_u32(0xff9933cc),
-    Color::from_u32(0xff6633cc), Color::from_u32(0xff3333cc), Color::from_u32(0xff0033cc), Color::from_u32(0xffff00cc),
-    Color::from_u32(0xffcc00cc), Color::from_u32(0xff9900cc), Color::from_u32(0xff6600cc), Color::from_u32(0xff3300cc),
-    Color::from_u32(0xff0000cc), Color::from_u32(0xffffff99), Color::from_u32(0xffccff99), Color::from_u32(0xff99ff99),
-    Color::from_u32(0xff66ff99), Color::from_u32(0xff33ff99), Color::from_u32(0xff00ff99), Color::from_u32(0xffffcc99),
-    Color::from_u32(0xffcccc99), Color::from_u32(0xff99cc99), Color::from_u32(0xff66cc99), Color::from_u32(0xff33cc99),
-    Color::from_u32(0xff00cc99), Color::from_u32(0xffff9999), Color::from_u32(0xffcc9999), Color::from_u32(0xff999999),
-    Color::from_u32(0xff669999), Color::from_u32(0xff339999), Color::from_u32(0xff009999), Color::from_u32(0xffff6699),
-    Color::from_u32(0xffcc6699), Color::from_u32(0xff996699), Color::from_u32(0xff666699), Color::from_u32(0xff336699),
-    Color::from_u32(0xff006699), Color::from_u32(0xffff3399), Color::from_u32(0xffcc3399), Color::from_u32(0xff993399),
-    Color::from_u32(0xff663399), Color::from_u32(0xff333399), Color::from_u32(0xff003399), Color::from_u32(0xffff0099),
-    Color::from_u32(0xffcc0099), Color::from_u32(0xff990099), Color::from_u32(0xff660099), Color::from_u32(0xff330099),
-    Color::from_u32(0xff000099), Color::from_u32(0xffffff66), Color::from_u32(0xffccff66), Color::from_u32(0xff99ff66),
-    Color::from_u32(0xff66ff66), Color::from_u32(0xff33ff66), Color::from_u32(0xff00ff66), Color::from_u32(0xffffcc66),
-    Color::from_u32(0xffcccc66), Color::from_u32(0xff99cc66), Color::from_u32(0xff66cc66), Color::from_u32(0xff33cc66),
-    Color::from_u32(0xff00cc66), Color::from_u32(0xffff9966), Color::from_u32(0xffcc9966), Color::from_u32(0xff999966),
-    Color::from_u32(0xff669966), Color::from_u32(0xff339966), Color::from_u32(0xff009966), Color::from_u32(0xffff6666),
-    Color::from_u32(0xffcc6666), Color::from_u32(0xff996666), Color::from_u32(0xff666666), Color::from_u32(0xff336666),
-    Color::from_u32(0xff006666), Color::from_u32(0xffff3366), Color::from_u32(0xffcc3366), Color::from_u32(0xff993366),
-    Color::from_u32(0xff663366), Color::from_u32(0xff333366), Color::from_u32(0xff003366), Color::from_u32(0xffff0066),
-    Color::from_u32(0xffcc0066), Color::from_u32(0xff990066), Color::from_u32(0xff660066), Color::from_u32(0xff330066),
-    Color::from_u32(0xff000066), Color::from_u32(0xffffff33), Color::from_u32(0xffccff33), Color::from_u32(0xff99ff33),
-    Color::from_u32(0xff66ff33), Color::from_u32(0xff33ff33), Color::from_u32(0xff00ff33), Color::from_u32(0xffffcc33),
-    Color::from_u32(0xffcccc33), Color::from_u32(0xff99cc33), Color::from_u32(0xff66cc33), Color::from_u32(0xff33cc33),
-    Color::from_u32(0xff00cc33), Color::from_u32(0xffff9933), Color::from_u32(0xffcc9933), Color::from_u32(0xff999933),
-    Color::from_u32(0xff669933), Color::from_u32(0xff339933), Color::from_u32(0xff009933), Color::from_u32(0xffff6633),
-    Color::from_u32(0xffcc6633), Color::from_u32(0xff996633), Color::from_u32(0xff666633), Color::from_u32(0xff336633),
-    Color::from_u32(0xff006633), Color::from_u32(0xffff3333), Color::from_u32(0xffcc3333), Color::from_u32(0xff993333),
-    Color::from_u32(0xff663333), Color::from_u32(0xff333333), Color::from_u32(0xff003333), Color::from_u32(0xffff0033),
-    Color::from_u32(0xffcc0033), Color::from_u32(0xff990033), Color::from_u32(0xff660033), Color::from_u32(0xff330033),
-    Color::from_u32(0xff000033), Color::from_u32(0xffffff00), Color::from_u32(0xffccff00), Color::from_u32(0xff99ff00),
-    Color::from_u32(0xff66ff00), Color::from_u32(0xff33ff00), Color::from_u32(0xff00ff00), Color::from_u32(0xffffcc00),
-    Color::from_u32(0xffcccc00), Color::from_u32(0xff99cc00), Color::from_u32(0xff66cc00), Color::from_u32(0xff33cc00),
-    Color::from_u32(0xff00cc00), Color::from_u32(0xffff9900), Color::from_u32(0xffcc9900), Color::from_u32(0xff999900),
-    Color::from_u32(0xff669900), Color::from_u32(0xff339900), Color::from_u32(0xff009900), Color::from_u32(0xffff6600),
-    Color::from_u32(0xffcc6600), Color::from_u32(0xff996600), Color::from_u32(0xff666600), Color::from_u32(0xff336600),
-    Color::from_u32(0xff006600), Color::from_u32(0xffff3300), Color::from_u32(0xffcc3300), Color::from_u32(0xff993300),
-    Color::from_u32(0xff663300), Color::from_u32(0xff333300), Color::from_u32(0xff003300), Color::from_u32(0xffff0000),
-    Color::from_u32(0xffcc0000), Color::from_u32(0xff990000), Color::from_u32(0xff660000), Color::from_u32(0xff330000),
-    Color::from_u32(0xff0000ee), Color::from_u32(0xff0000dd), Color::from_u32(0xff0000bb), Color::from_u32(0xff0000aa),
-    Color::from_u32(0xff000088), Color::from_u32(0xff000077), Color::from_u32(0xff000055), Color::from_u32(0xff000044),
-    Color::from_u32(0xff000022), Color::from_u32(0xff000011), Color::from_u32(0xff00ee00), Color::from_u32(0xff00dd00),
-    Color::from_u32(0xff00bb00), Color::from_u32(0xff00aa00), Color::from_u32(0xff008800), Color::from_u32(0xff007700),
-    Color::from_u32(0xff005500), Color::from_u32(0xff004400), Color::from_u32(0xff002200), Color::from_u32(0xff001100),
-    Color::from_u32(0xffee0000), Color::from_u32(0xffdd0000), Color::from_u32(0xffbb0000), Color::from_u32(0xffaa0000),
-    Color::from_u32(0xff880000), Color::from_u32(0xff770000), Color::from_u32(0xff550000), Color::from_u32(0xff440000),
-    Color::from_u32(0xff220000), Color::from_u32(0xff110000), Color::from_u32(0xffeeeeee), Color::from_u32(0xffdddddd),
-    Color::from_u32(0xffbbbbbb), Color::from_u32(0xffaaaaaa), Color::from_u32(0xff888888), Color::from_u32(0xff777777),
-    Color::from_u32(0xff555555), Color::from_u32(0xff444444), Color::from_u32(0xff222222), Color::from_u32(0xff111111),
+    pub attributes: Dict,
+}
+
+#[allow(clippy::unreadable_literal)]
+pub(crate) const DEFAULT_PALETTE: [Color; 256] = [
+    Color::from_abgr(0x00000000), Color::from_abgr(0xffffffff), Color::from_abgr(0xffccffff), Color::from_abgr(0xff99ffff),
+    Color::from_abgr(0xff66ffff), Color::from_abgr(0xff33ffff), Color::from_abgr(0xff00ffff), Color::from_abgr(0xffffccff),
+    Color::from_abgr(0xffccccff), Color::from_abgr(0xff99ccff), Color::from_abgr(0xff66ccff), Color::from_abgr(0xff33ccff),
+    Color::from_abgr(0xff00ccff), Color::from_abgr(0xffff99ff), Color::from_abgr(0xffcc99ff), Color::from_abgr(0xff9999ff),
+    Color::from_abgr(0xff6699ff), Color::from_abgr(0xff3399ff), Color::from_abgr(0xff0099ff), Color::from_abgr(0xffff66ff),
+    Color::from_abgr(0xffcc66ff), Color::from_abgr(0xff9966ff), Color::from_abgr(0xff6666ff), Color::from_abgr(0xff3366ff),
+    Color::from_abgr(0xff0066ff), Color::from_abgr(0xffff33ff), Color::from_abgr(0xffcc33ff), Color::from_abgr(0xff9933ff),
+    Color::from_abgr(0xff6633ff), Color::from_abgr(0xff3333ff), Color::from_abgr(0xff0033ff), Color::from_abgr(0xffff00ff),
+    Color::from_abgr(0xffcc00ff), Color::from_abgr(0xff9900ff), Color::from_abgr(0xff6600ff), Color::from_abgr(0xff3300ff),
+    Color::from_abgr(0xff0000ff), Color::from_abgr(0xffffffcc), Color::from_abgr(0xffccffcc), Color::from_abgr(0xff99ffcc),
+    Color::from_abgr(0xff66ffcc), Color::from_abgr(0xff33ffcc), Color::from_abgr(0xff00ffcc), Color::from_abgr(0xffffcccc),
+    Color::from_abgr(0xffcccccc), Color::from_abgr(0xff99cccc), Color::from_abgr(0xff66cccc), Color::from_abgr(0xff33cccc),
+    Color::from_abgr(0xff00cccc), Color::from_abgr(0xffff99cc), Color::from_abgr(0xffcc99cc), Color::from_abgr(0xff9999cc),
+    Color::from_abgr(0xff6699cc), Color::from_abgr(0xff3399cc), Color::from_abgr(0xff0099cc), Color::from_abgr(0xffff66cc),
+    Color::from_abgr(0xffcc66cc), Color::from_abgr(0xff9966cc), Color::from_abgr(0xff6666cc), Color::from_abgr(0xff3366cc),
+    Color::from_abgr(0xff0066cc), Color::from_abgr(0xffff33cc), Color::from_abgr(0xffcc33cc), Color::from_abgr(0xff9933cc),
+    Color::from_abgr(0xff6633cc), Color::from_abgr(0xff3333cc), Color::from_abgr(0xff0033cc), Color::from_abgr(0xffff00cc),
+    Color::from_abgr(0xffcc00cc), Color::from_abgr(0xff9900cc), Color::from_abgr(0xff6600cc), Color::from_abgr(0xff3300cc),
+    Color::from_abgr(0xff0000cc), Color::from_abgr(0xffffff99), Color::from_abgr(0xffccff99), Color::from_abgr(0xff99ff99),
+    Color::from_abgr(0xff66ff99), Color::from_abgr(0xff33ff99), Color::from_abgr(0xff00ff99), Color::from_abgr(0xffffcc99),
+    Color::from_abgr(0xffcccc99), Color::from_abgr(0xff99cc99), Color::from_abgr(0xff66cc99), Color::from_abgr(0xff33cc99),
+    Color::from_abgr(0xff00cc99), Color::from_abgr(0xffff9999), Color::from_abgr(0xffcc9999), Color::from_abgr(0xff999999),
+    Color::from_abgr(0xff669999), Color::from_abgr(0xff339999), Color::from_abgr(0xff009999), Color::from_abgr(0xffff6699),
+    Color::from_abgr(0xffcc6699), Color::from_abgr(0xff996699), Color::from_abgr(0xff666699), Color::from_abgr(0xff336699),
+    Color::from_abgr(0xff006699), Color::from_abgr(0xffff3399), Color::from_abgr(0xffcc3399), Color::from_abgr(0xff993399),
+    Color::from_abgr(0xff663399), Color::from_abgr(0xff333399), Color::from_abgr(0xff003399), Color::from_abgr(0xffff0099),
+    Color::from_abgr(0xffcc0099), Color::from_abgr(0xff990099), Color::from_abgr(0xff660099), Color::from_abgr(0xff330099),
+    Color::from_abgr(0xff000099), Color::from_abgr(0xffffff66), Color::from_abgr(0xffccff66), Color::from_abgr(0xff99ff66),
+    Color::from_abgr(0xff66ff66), Color::from_abgr(0xff33ff66), Color::from_abgr(0xff00ff66), Color::from_abgr(0xffffcc66),
+    Color::from_abgr(0xffcccc66), Color::from_abgr(0xff99cc66), Color::from_abgr(0xff66cc66), Color::from_abgr(0xff33cc66),
+    Color::from_abgr(0xff00cc66), Color::from_abgr(0xffff9966), Color::from_abgr(0xffcc9966), Color::from_abgr(0xff999966),
+    Color::from_abgr(0xff669966), Color::from_abgr(0xff339966), Color::from_abgr(0xff009966), Color::from_abgr(0xffff6666),
+    Color::from_abgr(0xffcc6666), Color::from_abgr(0xff996666), Color::from_abgr(0xff666666), Color::from_abgr(0xff336666),
+    Color::from_abgr(0xff006666), Color::from_abgr(0xffff3366), Color::from_abgr(0xffcc3366), Color::from_abgr(0xff993366),
+    Color::from_abgr(0xff663366), Color::from_abgr(0xff333366), Color::from_abgr(0xff003366), Color::from_abgr(0xffff0066),
+    Color::from_abgr(0xffcc0066), Color::from_abgr(0xff990066), Color::from_abgr(0xff660066), Color::from_abgr(0xff330066),
+    Color::from_abgr(0xff000066), Color::from_abgr(0xffffff33), Color::from_abgr(0xffccff33), Color::from_abgr(0xff99ff33),
+    Color::from_abgr(0xff66ff33), Color::from_abgr(0xff33ff33), Color::from_abgr(0xff00ff33), Color::from_abgr(0xffffcc33),
+    Color::from_abgr(0xffcccc33), Color::from_abgr(0xff99cc33), Color::from_abgr(0xff66cc33), Color::from_abgr(0xff33cc33),
+    Color::from_abgr(0xff00cc33), Color::from_abgr(0xffff9933), Color::from_abgr(0xffcc9933), Color::from_abgr(0xff999933),
+    Color::from_abgr(0xff669933), Color::from_abgr(0xff339933), Color::from_abgr(0xff009933), Color::from_abgr(0xffff6633),
+    Color::from_abgr(0xffcc6633), Color::from_abgr(0xff996633), Color::from_abgr(0xff666633), Color::from_abgr(0xff336633),
+    Color::from_abgr(0xff006633), Color::from_abgr(0xffff3333), Color::from_abgr(0xffcc3333), Color::from_abgr(0xff993333),
+    Color::from_abgr(0xff663333), Color::from_abgr(0xff333333), Color::from_abgr(0xff003333), Color::from_abgr(0xffff0033),
+    Color::from_abgr(0xffcc0033), Color::from_abgr(0xff990033), Color::from_abgr(0xff660033), Color::from_abgr(0xff330033),
+    Color::from_abgr(0xff000033), Color::from_abgr(0xffffff00), Color::from_abgr(0xffccff00), Color::from_abgr(0xff99ff00),
+    Color::from_abgr(0xff66ff00), Color::from_abgr(0xff33ff00), Color::from_abgr(0xff00ff00), Color::from_abgr(0xffffcc00),
+    Color::from_abgr(0xffcccc00), Color::from_abgr(0xff99cc00), Color::from_abgr(0xff66cc00), Color::from_abgr(0xff33cc00),
+    Color::from_abgr(0xff00cc00), Color::from_abgr(0xffff9900), Color::from_abgr(0xffcc9900), Color::from_abgr(0xff999900),
+    Color::from_abgr(0xff669900), Color::from_abgr(0xff339900), Color::from_abgr(0xff009900), Color::from_abgr(0xffff6600),
+    Color::from_abgr(0xffcc6600), Color::from_abgr(0xff996600), Color::from_abgr(0xff666600), Color::from_abgr(0xff336600),
+    Color::from_abgr(0xff006600), Color::from_abgr(0xffff3300), Color::from_abgr(0xffcc3300), Color::from_abgr(0xff993300),
+    Color::from_abgr(0xff663300), Color::from_abgr(0xff333300), Color::from_abgr(0xff003300), Color::from_abgr(0xffff0000),
+    Color::from_abgr(0xffcc0000), Color::from_abgr(0xff990000), Color::from_abgr(0xff660000), Color::from_abgr(0xff330000),
+    Color::from_abgr(0xff0000ee), Color::from_abgr(0xff0000dd), Color::from_abgr(0xff0000bb), Color::from_abgr(0xff0000aa),
+    Color::from_abgr(0xff000088), Color::from_abgr(0xff000077), Color::from_abgr(0xff000055), Color::from_abgr(0xff000044),
+    Color::from_abgr(0xff000022), Color::from_abgr(0xff000011), Color::from_abgr(0xff00ee00), Color::from_abgr(0xff00dd00),
+    Color::from_abgr(0xff00bb00), Color::from_abgr(0xff00aa00), Color::from_abgr(0xff008800), Color::from_abgr(0xff007700),
+    Color::from_abgr(0xff005500), Color::from_abgr(0xff004400), Color::from_abgr(0xff002200), Color::from_abgr(0xff001100),
+    Color::from_abgr(0xffee0000), Color::from_abgr(0xffdd0000), Color::from_abgr(0xffbb0000), Color::from_abgr(0xffaa0000),
+    Color::from_abgr(0xff880000), Color::from_abgr(0xff770000), Color::from_abgr(0xff550000), Color::from_abgr(0xff440000),
+    Color::from_abgr(0xff220000), Color::from_abgr(0xff110000), Color::from_abgr(0xffeeeeee), Color::from_abgr(0xffdddddd),
+    Color::from_abgr(0xffbbbbbb), Color::from_abgr(0xffaaaaaa), Color::from_abgr(0xff888888), Color::from_abgr(0xff777777),
+    Color::from_abgr(0xff555555), Color::from_abgr(0xff444444), Color::from_abgr(0xff222222), Color::from_abgr(0xff111111),
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::{Color, Dict, Frame, Rotation, ShapeKeyframe, ShapeNode, Size, TransformNode, VoxFile};
+
+    fn all_rotations() -> Vec<Rotation> {
+        (0..=u8::MAX).map(Rotation).filter(Rotation::is_valid).collect()
+    }
+
+    #[test]
+    fn test_default_palette() {
+        let file = VoxFile::default();
+        assert_eq!(file.palette[0], Color { name: None, r: 0, g: 0, b: 0, a: 0 });
+        assert_eq!(file.palette[1], Color { name: None, r: 255, g: 255, b: 255, a: 255 });
+        assert_eq!(file.palette[2], Color { name: None, r: 255, g: 255, b: 0xcc, a: 255 });
+        assert_eq!(file.palette[216], Color { name: None, r: 0xee, g: 0, b: 0, a: 255 });
+        assert_eq!(file.palette[2].to_abgr(), 0xffcc_ffff);
+        assert_eq!(Color::from_abgr(0xffcc_ffff), file.palette[2]);
+        assert_eq!(Color::from_u32(0xffff_ccff), file.palette[2]);
+    }
+
+    #[test]
+    #[allow(clippy::identity_op)]
+    fn test_rotation_spec_example() {
+        let rotation = Rotation((1 << 0) | (2 << 2) | (0 << 4) | (1 << 5) | (1 << 6));
+        let expected = [[0.0, 1.0, 0.0], [0.0, 0.0, -1.0], [-1.0, 0.0, 0.0]];
+        assert!(rotation.to_matrix() == expected);
+        assert_eq!(Rotation::from_matrix(expected), Some(rotation));
+        assert_eq!(rotation.apply([1, 2, 3]), [2, -3, -1]);
+        assert_eq!(rotation.apply_size(Size { x: 1, y: 2, z: 3 }), Size { x: 2, y: 3, z: 1 });
+    }
+
+    #[test]
+    fn test_rotation_round_trip() {
+        let rotations = all_rotations();
+        assert_eq!(rotations.len(), 48);
+        for rotation in rotations {
+            assert_eq!(Rotation::from_matrix(rotation.to_matrix()), Some(rotation));
+        }
+        assert_eq!(Rotation::from_matrix([[1.0, 1.0, 0.0], [0.0, 0.0, 1.0], [0.0, 0.0, 0.0]]), None);
+        assert_eq!(Rotation::from_matrix([[0.5, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]), None);
+        assert_eq!(Rotation::from_matrix([[1.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]]), None);
+        assert!(!Rotation(3).is_valid());
+        assert!(!Rotation(0).is_valid());
+    }
+
+    #[test]
+    fn test_rotation_composition() {
+        let identity = Rotation::identity();
+        assert!(identity.is_valid());
+        let point = [1, -2, 3];
+        assert_eq!(identity.apply(point), point);
+        for lhs in all_rotations() {
+            assert_eq!(lhs * lhs.inverse(), identity);
+            assert_eq!(lhs.inverse().apply(lhs.apply(point)), point);
+            for rhs in all_rotations() {
+                assert_eq!((lhs * rhs).apply(point), lhs.apply(rhs.apply(point)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_frame_dict() {
+        let mut dict = Dict::new();
+        dict.insert("_r", "17");
+        dict.insert("_t", "-25 0 20");
+        dict.insert("_f", "12");
+        let frame = Frame::from_dict(&dict).expect("Invalid frame.");
+        assert_eq!(frame, Frame { rotation: Rotation(17), translation: [-25, 0, 20], frame_index: 12 });
+        assert_eq!(frame.to_dict(), dict);
+        assert_eq!(Frame::from_dict(&Dict::new()).expect("Invalid frame."), Frame::default());
+        assert!(Frame::default().to_dict().is_empty());
+
+        dict.insert("_t", "1 2");
+        assert!(Frame::from_dict(&dict).is_err());
+        dict.insert("_t", "1 2 3");
+        dict.insert("_r", "3");
+        assert!(Frame::from_dict(&dict).is_err());
+    }
+
+    #[test]
+    fn test_transform_at() {
+        let mut node = TransformNode {
+            id: 0,
+            attrib: Dict::new(),
+            child_node_id: 1,
+            reserved_id: -1,
+            layer_id: 0,
+            frames: vec![],
+        };
+        assert_eq!(node.transform_at(5).expect("Invalid frame."), Frame::default());
+        let keyframes = [
+            Frame { rotation: Rotation::identity(), translation: [0, 0, 1], frame_index: 2 },
+            Frame { rotation: Rotation(17), translation: [0, 0, 2], frame_index: 6 },
+        ];
+        node.frames.push(Dict::new());
+        node.frames[0].insert("_name", "kept");
+        node.set_keyframes(&keyframes);
+        assert_eq!(node.frames[0].get("_name"), Some("kept"));
+        assert_eq!(node.keyframes().expect("Invalid frame."), keyframes.to_vec());
+        assert_eq!(node.transform_at(0).expect("Invalid frame."), keyframes[0]);
+        assert_eq!(node.transform_at(5).expect("Invalid frame."), keyframes[0]);
+        assert_eq!(node.transform_at(6).expect("Invalid frame."), keyframes[1]);
+        assert_eq!(node.transform_at(100).expect("Invalid frame."), keyframes[1]);
+    }
+
+    #[test]
+    fn test_model_at() {
+        let mut node = ShapeNode { id: 0, attrib: Dict::new(), models: vec![(3, Dict::new())] };
+        assert_eq!(node.model_at(10).expect("Invalid keyframe."), Some(3));
+        let keyframes = [
+            ShapeKeyframe { model_id: 0, frame_index: 0 },
+            ShapeKeyframe { model_id: 1, frame_index: 4 },
+            ShapeKeyframe { model_id: 2, frame_index: 8 },
+        ];
+        node.set_keyframes(&keyframes);
+        assert_eq!(node.models[1].1.get("_f"), Some("4"));
+        assert_eq!(node.keyframes().expect("Invalid keyframe."), keyframes.to_vec());
+        assert_eq!(node.model_at(3).expect("Invalid keyframe."), Some(0));
+        assert_eq!(node.model_at(4).expect("Invalid keyframe."), Some(1));
+        assert_eq!(node.model_at(9).expect("Invalid keyframe."), Some(2));
+        node.models[2].1.insert("_f", "soon");
+        assert!(node.model_at(0).is_err());
+        node.set_keyframes(&[]);
+        assert_eq!(node.model_at(0).expect("Invalid keyframe."), None);
+    }
+}
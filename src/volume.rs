@@ -0,0 +1,240 @@
+//
+// Copyright 2021 Hans W. Uhlig. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use crate::error::DotVoxResult;
+use crate::scene::Matrix;
+use crate::types::{Layer, Model, Size, Voxel, VoxFile};
+
+/// A dense region of world space holding the voxels of a flattened scene.
+///
+/// Unlike a [`Model`] a Volume is not limited to 256 voxels on a side.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Volume {
+    /// World space position of the Volume's minimum corner.
+    pub origin: [i32; 3],
+    /// The size of the Volume in voxels.
+    pub size: Size,
+    /// The list of Voxels in the Volume, relative to `origin`.
+    pub voxels: Vec<VolumeVoxel>,
+}
+
+/// A voxel in a [`Volume`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct VolumeVoxel {
+    /// The X coordinate of the voxel.
+    pub x: u32,
+    /// The Y coordinate of the voxel.
+    pub y: u32,
+    /// The Z coordinate of the voxel.
+    pub z: u32,
+    /// Index in the Color Palette.
+    pub i: u8,
+}
+
+impl Volume {
+    /// Convert this Volume into a Model, if it fits within the 256 voxel limit of a Model.
+    pub fn to_model(&self, id: u32) -> Option<Model> {
+        if self.size.x > 256 || self.size.y > 256 || self.size.z > 256 {
+            return None;
+        }
+        let voxels = self.voxels
+            .iter()
+            .map(|voxel| Some(Voxel {
+                x: u8::try_from(voxel.x).ok()?,
+                y: u8::try_from(voxel.y).ok()?,
+                z: u8::try_from(voxel.z).ok()?,
+                i: voxel.i,
+            }))
+            .collect::<Option<Vec<Voxel>>>()?;
+        Some(Model { id, size: self.size, voxels })
+    }
+}
+
+impl VoxFile {
+    /// Bake every visible model instance in the scene into a single Volume.
+    ///
+    /// Each model is placed by its world transform, including rotations. Hidden instances and
+    /// instances on hidden layers are skipped, and where instances overlap the one later in the
+    /// scene wins.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the scene graph is malformed.
+    pub fn flatten(&self) -> DotVoxResult<Volume> {
+        let mut world: HashMap<[i32; 3], u8> = HashMap::new();
+        for instance in self.instances()? {
+            if instance.hidden || self.layer(instance.layer_id).is_some_and(Layer::is_hidden) {
+                continue;
+            }
+            let model = &self.models[instance.model_id as usize];
+            for voxel in &model.voxels {
+                world.insert(place(&instance.world_matrix, model.size, voxel), voxel.i);
+            }
+        }
+        if world.is_empty() {
+            return Ok(Volume { origin: [0; 3], size: Size { x: 0, y: 0, z: 0 }, voxels: Vec::new() });
+        }
+        let mut min = [i32::MAX; 3];
+        let mut max = [i32::MIN; 3];
+        for position in world.keys() {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(position[axis]);
+                max[axis] = max[axis].max(position[axis]);
+            }
+        }
+        let extent = |axis: usize| u32::try_from(i64::from(max[axis]) - i64::from(min[axis]) + 1)
+            .unwrap_or(u32::MAX);
+        let offset = |position: &[i32; 3], axis: usize| {
+            u32::try_from(i64::from(position[axis]) - i64::from(min[axis])).unwrap_or(u32::MAX)
+        };
+        let mut voxels: Vec<VolumeVoxel> = world
+            .iter()
+            .map(|(position, i)| VolumeVoxel {
+                x: offset(position, 0),
+                y: offset(position, 1),
+                z: offset(position, 2),
+                i: *i,
+            })
+            .collect();
+        voxels.sort_by_key(|voxel| (voxel.z, voxel.y, voxel.x));
+        Ok(Volume { origin: min, size: Size { x: extent(0), y: extent(1), z: extent(2) }, voxels })
+    }
+}
+
+/// Find the world position of a model's voxel.
+///
+/// Models rotate about their centre, which for even sizes falls between voxels. Working with
+/// doubled voxel centres keeps this exact: the centre of voxel `v` in a model of size `s` is at
+/// `2v + 1 - s`, and after rotating the world voxel is `floor(centre / 2) + translation`.
+#[allow(clippy::cast_possible_truncation)]
+fn place(matrix: &Matrix, size: Size, voxel: &Voxel) -> [i32; 3] {
+    let centre = [
+        2 * i64::from(voxel.x) + 1 - i64::from(size.x),
+        2 * i64::from(voxel.y) + 1 - i64::from(size.y),
+        2 * i64::from(voxel.z) + 1 - i64::from(size.z),
+    ];
+    let mut position = [0; 3];
+    for (axis, value) in position.iter_mut().enumerate() {
+        let row = &matrix[axis];
+        let rotated: i64 = (0..3).map(|column| row[column].round() as i64 * centre[column]).sum();
+        *value = (rotated.div_euclid(2) + row[3].round() as i64) as i32;
+    }
+    position
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        Dict, GroupNode, Layer, Model, SceneNode, ShapeNode, Size, TransformNode, Voxel, VoxFile,
+    };
+    use super::VolumeVoxel;
+
+    fn scene(rotation: &str, hidden: bool) -> VoxFile {
+        let mut file = VoxFile::default();
+        file.models.push(Model {
+            id: 0,
+            size: Size { x: 2, y: 1, z: 1 },
+            voxels: vec![Voxel { x: 0, y: 0, z: 0, i: 1 }, Voxel { x: 1, y: 0, z: 0, i: 2 }],
+        });
+        let mut frame = Dict::new();
+        frame.insert("_r", rotation);
+        frame.insert("_t", "5 0 0");
+        file.scenegraph = vec![
+            SceneNode::Transform(TransformNode {
+                id: 0,
+                attrib: Dict::new(),
+                child_node_id: 1,
+                reserved_id: -1,
+                layer_id: u32::MAX,
+                frames: vec![Dict::new()],
+            }),
+            SceneNode::Group(GroupNode { id: 1, attrib: Dict::new(), children: vec![2, 4] }),
+            SceneNode::Transform(TransformNode {
+                id: 2,
+                attrib: Dict::new(),
+                child_node_id: 3,
+                reserved_id: -1,
+                layer_id: 0,
+                frames: vec![Dict::new()],
+            }),
+            SceneNode::Shape(ShapeNode { id: 3, attrib: Dict::new(), models: vec![(0, Dict::new())] }),
+            SceneNode::Transform(TransformNode {
+                id: 4,
+                attrib: Dict::new(),
+                child_node_id: 5,
+                reserved_id: -1,
+                layer_id: 1,
+                frames: vec![frame],
+            }),
+            SceneNode::Shape(ShapeNode { id: 5, attrib: Dict::new(), models: vec![(0, Dict::new())] }),
+        ];
+        let mut attributes = Dict::new();
+        attributes.insert("_hidden", if hidden { "1" } else { "0" });
+        file.layers = vec![
+            Layer { id: 0, attributes: Dict::new(), reserved: -1 },
+            Layer { id: 1, attributes, reserved: -1 },
+        ];
+        file
+    }
+
+    #[test]
+    fn test_flatten_rotated() {
+        // Rotate 180 degrees about Z.
+        let volume = scene("52", false).flatten().expect("Error flattening scene.");
+        assert_eq!(volume.origin, [-1, 0, 0]);
+        assert_eq!(volume.size, Size { x: 7, y: 1, z: 1 });
+        assert_eq!(volume.voxels, vec![
+            VolumeVoxel { x: 0, y: 0, z: 0, i: 1 },
+            VolumeVoxel { x: 1, y: 0, z: 0, i: 2 },
+            VolumeVoxel { x: 5, y: 0, z: 0, i: 2 },
+            VolumeVoxel { x: 6, y: 0, z: 0, i: 1 },
+        ]);
+    }
+
+    #[test]
+    fn test_flatten_hidden_layer() {
+        let volume = scene("52", true).flatten().expect("Error flattening scene.");
+        assert_eq!(volume.origin, [-1, 0, 0]);
+        assert_eq!(volume.size, Size { x: 2, y: 1, z: 1 });
+        let model = volume.to_model(0).expect("Volume should fit in a model.");
+        assert_eq!(model.voxels, vec![Voxel { x: 0, y: 0, z: 0, i: 1 }, Voxel { x: 1, y: 0, z: 0, i: 2 }]);
+    }
+
+    #[test]
+    fn test_flatten_hidden_node() {
+        let mut file = scene("52", false);
+        if let SceneNode::Transform(node) = &mut file.scenegraph[4] {
+            node.attrib.insert("_hidden", "1");
+        }
+        let volume = file.flatten().expect("Error flattening scene.");
+        assert_eq!(volume.origin, [-1, 0, 0]);
+        assert_eq!(volume.size, Size { x: 2, y: 1, z: 1 });
+    }
+
+    #[test]
+    fn test_flatten_streetlamp() {
+        let file = VoxFile::open("vox/streetlamp.vox").expect("Error loading test file.");
+        let volume = file.flatten().expect("Error flattening scene.");
+        let total: usize = file.models.iter().map(|model| model.voxels.len()).sum();
+        assert!(volume.voxels.len() <= total);
+        assert!(volume.voxels.iter().all(|voxel| {
+            voxel.x < volume.size.x && voxel.y < volume.size.y && voxel.z < volume.size.z
+        }));
+        assert!(volume.to_model(0).is_some());
+    }
+}
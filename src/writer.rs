@@ -14,13 +14,442 @@
 // limitations under the License.
 //
 
+#![allow(non_snake_case)]
+
 use byteorder::{LittleEndian, WriteBytesExt};
-use std::io::{Write, Result};
-use super::types::Chunk;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::path::Path;
+use crate::error::DotVoxResult;
+use super::types::{
+    DEFAULT_PALETTE, MAGIC_NUMBER, Camera, Chunk, Color, Dict, GroupNode, Layer, Material, MaterialV1, MaterialV2,
+    Pack, SceneNode, ShapeNode, Size, TransformNode, Voxel, VoxFile,
+};
+
+impl VoxFile {
+    /// Serialize this file in .vox format to `output`.
+    ///
+    /// Models are written in order, so a model's id is its index in `models`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to `output` fails or the file contains data that can not be
+    /// represented in the .vox format.
+    pub fn write_to<W: Write>(&self, output: W) -> DotVoxResult<()> {
+        write_chunks(output, self.version, std::slice::from_ref(&self.to_chunk()))
+    }
 
+    /// Write this file in .vox format to the file at `path`, replacing it if it exists.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can not be written.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> DotVoxResult<()> {
+        let mut output = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.write_to(&mut output)?;
+        output.flush()?;
+        Ok(())
+    }
+
+    /// Build the MAIN chunk holding all of this file's data.
+    pub(crate) fn to_chunk(&self) -> Chunk {
+        let mut children = Vec::new();
+        if self.pack {
+            children.push(Chunk::PACK(Pack(u32::try_from(self.models.len()).unwrap_or(u32::MAX))));
+        }
+        for model in &self.models {
+            children.push(Chunk::SIZE(model.size));
+            children.push(Chunk::XYZI(model.voxels.clone()));
+        }
+        for node in &self.scenegraph {
+            children.push(match node {
+                SceneNode::Transform(node) => Chunk::nTRN(node.clone()),
+                SceneNode::Group(node) => Chunk::nGRP(node.clone()),
+                SceneNode::Shape(node) => Chunk::nSHP(node.clone()),
+            });
+        }
+        children.extend(self.layers.iter().cloned().map(Chunk::LAYR));
+        if self.chunk_order.iter().any(|id| id == "RGBA") || !self.has_default_palette() {
+            let mut colors = self.palette.to_vec();
+            colors.rotate_left(1);
+            children.push(Chunk::RGBA(colors));
+        }
+        if let Some(index_map) = &self.index_map {
+            children.push(Chunk::IMAP(index_map.to_vec()));
+        }
+        for material in &self.materials {
+            children.push(match material {
+                Material::V1(material) => Chunk::MATT(material.clone()),
+                Material::V2(material) => Chunk::MATL(material.clone()),
+            });
+        }
+        children.extend(self.render_objects.iter().cloned().map(Chunk::rOBJ));
+        children.extend(self.cameras.iter().cloned().map(Chunk::rCAM));
+        if !self.notes.is_empty() {
+            children.push(Chunk::NOTE(self.notes.clone()));
+        }
+        if !self.chunk_order.is_empty() {
+            children = self.reorder(children);
+        }
+        for (index, chunk) in &self.unknown_chunks {
+            children.insert((*index).min(children.len()), chunk.clone());
+        }
+        Chunk::MAIN(children)
+    }
+
+    /// Whether the palette colors are `MagicaVoxel`'s default ones, which need no RGBA chunk.
+    fn has_default_palette(&self) -> bool {
+        self.palette.iter().zip(DEFAULT_PALETTE.iter()).all(|(a, b)| a.to_abgr() == b.to_abgr())
+    }
+
+    /// Put `children` in the order given by `chunk_order`, taking the chunks of each id in turn.
+    /// Chunks left over, such as models added since reading, go at the end.
+    fn reorder(&self, children: Vec<Chunk>) -> Vec<Chunk> {
+        let mut remaining: Vec<Option<Chunk>> = children.into_iter().map(Some).collect();
+        let mut next: HashMap<&str, usize> = HashMap::new();
+        let mut ordered = Vec::with_capacity(remaining.len());
+        for id in &self.chunk_order {
+            let start = next.get(id.as_str()).copied().unwrap_or(0);
+            let found = remaining[start..]
+                .iter()
+                .position(|chunk| chunk.as_ref().is_some_and(|chunk| chunk.id() == id));
+            match found {
+                Some(offset) => {
+                    next.insert(id, start + offset + 1);
+                    ordered.extend(remaining[start + offset].take());
+                }
+                None => {
+                    next.insert(id, remaining.len());
+                }
+            }
+        }
+        ordered.extend(remaining.into_iter().flatten());
+        ordered
+    }
+}
+
+/// Write a .vox file of version `version` holding `chunks` to `output`.
+///
+/// This is the counterpart of [`read_chunks`](crate::read_chunks), writing chunks as they are
+/// without checking they make a valid file.
+///
+/// # Errors
+///
+/// Returns an error if writing to `output` fails or a chunk id is not four bytes long.
+pub fn write_chunks<W: Write>(mut output: W, version: u32, chunks: &[Chunk]) -> DotVoxResult<()> {
+    output.write_all(MAGIC_NUMBER.as_bytes())?;
+    output.write_u32::<LittleEndian>(version)?;
+    for chunk in chunks {
+        write_chunk(&mut output, chunk)?;
+    }
+    Ok(())
+}
+
+/// Write a chunk, its content and all of its children to `output`.
 pub fn write_chunk<T: Write>(output: &mut T, chunk: &Chunk) -> Result<()> {
+    let mut content = Vec::new();
     match chunk {
-        _ => output.write_i32::<LittleEndian>(0)?,
+        Chunk::MAIN(_) => {}
+        Chunk::SIZE(size) => write_SIZE(&mut content, size)?,
+        Chunk::XYZI(voxels) => write_XYZI(&mut content, voxels)?,
+        Chunk::PACK(pack) => write_PACK(&mut content, pack)?,
+        Chunk::RGBA(colors) => write_RGBA(&mut content, colors)?,
+        Chunk::MATT(material) => write_MATT(&mut content, material)?,
+        Chunk::MATL(material) => write_MATL(&mut content, material)?,
+        Chunk::rOBJ(attributes) => write_DICT(&mut content, attributes)?,
+        Chunk::rCAM(camera) => write_rCAM(&mut content, camera)?,
+        Chunk::IMAP(index_map) => content.extend_from_slice(index_map),
+        Chunk::NOTE(notes) => write_NOTE(&mut content, notes)?,
+        Chunk::nTRN(node) => write_nTRN(&mut content, node)?,
+        Chunk::nGRP(node) => write_nGRP(&mut content, node)?,
+        Chunk::nSHP(node) => write_nSHP(&mut content, node)?,
+        Chunk::LAYR(layer) => write_LAYR(&mut content, layer)?,
+        Chunk::Unknown { contents, .. } => content.extend_from_slice(contents),
+    }
+    let mut children = Vec::new();
+    for child in chunk.children() {
+        write_chunk(&mut children, child)?;
+    }
+    let kind = chunk.id();
+    if kind.len() != 4 {
+        return Err(Error::new(ErrorKind::InvalidData, format!("Invalid chunk id: {kind:?}")));
+    }
+    output.write_all(kind.as_bytes())?;
+    write_length(output, content.len())?;
+    write_length(output, children.len())?;
+    output.write_all(&content)?;
+    output.write_all(&children)?;
+    Ok(())
+}
+
+fn write_PACK<T: Write>(output: &mut T, pack: &Pack) -> Result<()> {
+    output.write_u32::<LittleEndian>(pack.0)
+}
+
+fn write_SIZE<T: Write>(output: &mut T, size: &Size) -> Result<()> {
+    output.write_u32::<LittleEndian>(size.x)?;
+    output.write_u32::<LittleEndian>(size.y)?;
+    output.write_u32::<LittleEndian>(size.z)
+}
+
+fn write_XYZI<T: Write>(output: &mut T, voxels: &[Voxel]) -> Result<()> {
+    write_length(output, voxels.len())?;
+    for voxel in voxels {
+        output.write_all(&[voxel.x, voxel.y, voxel.z, voxel.i])?;
     }
     Ok(())
 }
+
+fn write_RGBA<T: Write>(output: &mut T, colors: &[Color]) -> Result<()> {
+    for color in colors {
+        output.write_all(&[color.r, color.g, color.b, color.a])?;
+    }
+    Ok(())
+}
+
+fn write_MATT<T: Write>(output: &mut T, material: &MaterialV1) -> Result<()> {
+    let properties = [
+        material.plastic,
+        material.roughness,
+        material.specular,
+        material.ior,
+        material.attenuation,
+        material.power,
+        material.glow,
+    ];
+    let mut property_bits = if material.is_total_power { 0x80 } else { 0 };
+    for (bit, property) in properties.iter().enumerate() {
+        if property.is_some() {
+            property_bits |= 1 << bit;
+        }
+    }
+    output.write_u32::<LittleEndian>(material.id)?;
+    output.write_u32::<LittleEndian>(material.kind)?;
+    output.write_f32::<LittleEndian>(material.weight)?;
+    output.write_u32::<LittleEndian>(property_bits)?;
+    for value in properties.iter().flatten() {
+        output.write_f32::<LittleEndian>(*value)?;
+    }
+    Ok(())
+}
+
+fn write_MATL<T: Write>(output: &mut T, material: &MaterialV2) -> Result<()> {
+    output.write_u32::<LittleEndian>(material.id)?;
+    write_DICT(output, &material.properties)
+}
+
+fn write_rCAM<T: Write>(output: &mut T, camera: &Camera) -> Result<()> {
+    output.write_u32::<LittleEndian>(camera.id)?;
+    write_DICT(output, &camera.attributes)
+}
+
+fn write_NOTE<T: Write>(output: &mut T, notes: &[String]) -> Result<()> {
+    write_length(output, notes.len())?;
+    for note in notes {
+        write_STRING(output, note)?;
+    }
+    Ok(())
+}
+
+fn write_nTRN<T: Write>(output: &mut T, node: &TransformNode) -> Result<()> {
+    output.write_u32::<LittleEndian>(node.id)?;
+    write_DICT(output, &node.attrib)?;
+    output.write_u32::<LittleEndian>(node.child_node_id)?;
+    output.write_i32::<LittleEndian>(node.reserved_id)?;
+    output.write_u32::<LittleEndian>(node.layer_id)?;
+    write_length(output, node.frames.len())?;
+    for frame in &node.frames {
+        write_DICT(output, frame)?;
+    }
+    Ok(())
+}
+
+fn write_nGRP<T: Write>(output: &mut T, node: &GroupNode) -> Result<()> {
+    output.write_u32::<LittleEndian>(node.id)?;
+    write_DICT(output, &node.attrib)?;
+    write_length(output, node.children.len())?;
+    for child in &node.children {
+        output.write_u32::<LittleEndian>(*child)?;
+    }
+    Ok(())
+}
+
+fn write_nSHP<T: Write>(output: &mut T, node: &ShapeNode) -> Result<()> {
+    output.write_u32::<LittleEndian>(node.id)?;
+    write_DICT(output, &node.attrib)?;
+    write_length(output, node.models.len())?;
+    for (model_id, attrib) in &node.models {
+        output.write_u32::<LittleEndian>(*model_id)?;
+        write_DICT(output, attrib)?;
+    }
+    Ok(())
+}
+
+fn write_LAYR<T: Write>(output: &mut T, layer: &Layer) -> Result<()> {
+    output.write_u32::<LittleEndian>(layer.id)?;
+    write_DICT(output, &layer.attributes)?;
+    output.write_i32::<LittleEndian>(layer.reserved)
+}
+
+fn write_DICT<T: Write>(output: &mut T, dict: &Dict) -> Result<()> {
+    write_length(output, dict.len())?;
+    for (key, value) in dict.iter() {
+        write_STRING(output, key)?;
+        write_STRING(output, value)?;
+    }
+    Ok(())
+}
+
+fn write_STRING<T: Write>(output: &mut T, string: &str) -> Result<()> {
+    write_length(output, string.len())?;
+    output.write_all(string.as_bytes())
+}
+
+/// Write a length or count as the u32 used throughout the format.
+fn write_length<T: Write>(output: &mut T, length: usize) -> Result<()> {
+    let length = u32::try_from(length)
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "Length exceeds u32::MAX"))?;
+    output.write_u32::<LittleEndian>(length)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Chunk, Dict, Layer, Material, MaterialV1, Model, Pack, Size, Voxel, VoxFile};
+
+    #[test]
+    fn test_write_model() {
+        let mut file = VoxFile::default();
+        file.models.push(Model {
+            id: 0,
+            size: Size { x: 2, y: 3, z: 4 },
+            voxels: vec![
+                Voxel { x: 0, y: 0, z: 0, i: 1 },
+                Voxel { x: 1, y: 2, z: 3, i: 200 },
+            ],
+        });
+        file.materials.push(Material::V1(MaterialV1 {
+            id: 1,
+            kind: 1,
+            weight: 0.5,
+            plastic: None,
+            roughness: Some(0.25),
+            specular: None,
+            ior: None,
+            attenuation: None,
+            power: Some(0.75),
+            glow: None,
+            is_total_power: true,
+        }));
+        file.layers.push(Layer { id: 0, attributes: Dict::new(), reserved: -1 });
+        let mut buffer = Vec::new();
+        file.write_to(&mut buffer).expect("Error writing file.");
+        assert_eq!(&buffer[0..4], b"VOX ");
+        let main_children = u32::from_le_bytes([buffer[16], buffer[17], buffer[18], buffer[19]]);
+        assert_eq!(main_children as usize, buffer.len() - 20);
+
+        let result = VoxFile::from_bytes(&buffer).expect("Error reading file.");
+        assert_eq!(result.models, file.models);
+        assert_eq!(result.materials, file.materials);
+        assert_eq!(result.palette[..], file.palette[..]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for entry in std::fs::read_dir("vox").expect("Error listing test files.") {
+            let path = entry.expect("Error listing test files.").path();
+            let original = std::fs::read(&path).expect("Error opening test file.");
+            let file = VoxFile::from_bytes(&original).expect("Error reading test file.");
+            let mut buffer = Vec::new();
+            file.write_to(&mut buffer).expect("Error writing file.");
+            assert!(buffer == original, "{} did not round trip", path.display());
+        }
+    }
+
+    #[test]
+    fn test_round_trip_unknown_chunk() {
+        let mut file = VoxFile::open("vox/3x3x3.vox").expect("Error loading test file.");
+        file.unknown_chunks.push((2, Chunk::Unknown {
+            kind: String::from("zTST"),
+            contents: vec![1, 2, 3],
+            children: vec![Chunk::PACK(Pack(7))],
+        }));
+        let mut buffer = Vec::new();
+        file.write_to(&mut buffer).expect("Error writing file.");
+        let result = VoxFile::from_bytes(&buffer).expect("Error reading file.");
+        assert_eq!(result.unknown_chunks.len(), 1);
+        assert_eq!(result.unknown_chunks[0].0, 2);
+        let mut rewritten = Vec::new();
+        result.write_to(&mut rewritten).expect("Error writing file.");
+        assert!(rewritten == buffer);
+    }
+
+    #[test]
+    fn test_rewrite_menger() {
+        let file = VoxFile::open("vox/menger.vox").expect("Error loading test file.");
+        let mut buffer = Vec::new();
+        file.write_to(&mut buffer).expect("Error writing file.");
+        let result = VoxFile::from_bytes(&buffer).expect("Error reading file.");
+        assert_eq!(result.models, file.models);
+        assert_eq!(result.materials, file.materials);
+    }
+
+    #[test]
+    fn test_chunk_round_trip() {
+        for entry in std::fs::read_dir("vox").expect("Error listing test files.") {
+            let path = entry.expect("Error listing test files.").path();
+            let original = std::fs::read(&path).expect("Error opening test file.");
+            let (version, chunks) = crate::read_chunks(&original).expect("Error reading test file.");
+            assert_eq!(version, 150);
+            assert_eq!(chunks.len(), 1);
+            assert_eq!(chunks[0].id(), "MAIN");
+            let mut buffer = Vec::new();
+            crate::write_chunks(&mut buffer, version, &chunks).expect("Error writing file.");
+            assert!(buffer == original, "{} did not round trip", path.display());
+        }
+    }
+
+    #[test]
+    fn test_edit_chunks() {
+        let original = std::fs::read("vox/3x3x3.vox").expect("Error opening test file.");
+        let (version, mut chunks) = crate::read_chunks(&original).expect("Error reading test file.");
+        let vendor = Chunk::Unknown { kind: String::from("zTST"), contents: vec![1, 2, 3], children: Vec::new() };
+        if let Chunk::MAIN(children) = &mut chunks[0] {
+            children.retain(|chunk| chunk.id() != "rOBJ");
+            children.insert(0, vendor.clone());
+        }
+        let mut buffer = Vec::new();
+        crate::write_chunks(&mut buffer, version, &chunks).expect("Error writing file.");
+        let (_, reread) = crate::read_chunks(&buffer).expect("Error reading file.");
+        assert_eq!(reread, chunks);
+        assert_eq!(reread[0].children()[0], vendor);
+
+        let file = VoxFile::from_bytes(&buffer).expect("Error reading file.");
+        assert!(file.render_objects.is_empty());
+        assert_eq!(file.unknown_chunks, vec![(0, vendor)]);
+    }
+
+    #[test]
+    fn test_chunk_order() {
+        let original = std::fs::read("vox/3x3x3.vox").expect("Error opening test file.");
+        let (version, chunks) = crate::read_chunks(&original).expect("Error reading test file.");
+        let moved: fn(&mut Vec<Chunk>) = |children| {
+            let index = children.iter().position(|chunk| chunk.id() == "RGBA").unwrap();
+            let rgba = children.remove(index);
+            children.insert(0, rgba);
+        };
+        let removed: fn(&mut Vec<Chunk>) = |children| children.retain(|chunk| chunk.id() != "RGBA");
+        for edit in &[moved, removed] {
+            let mut chunks = chunks.clone();
+            if let Chunk::MAIN(children) = &mut chunks[0] {
+                edit(children);
+            }
+            let mut buffer = Vec::new();
+            crate::write_chunks(&mut buffer, version, &chunks).expect("Error writing file.");
+            let file = VoxFile::from_bytes(&buffer).expect("Error reading file.");
+            let mut rewritten = Vec::new();
+            file.write_to(&mut rewritten).expect("Error writing file.");
+            assert_eq!(rewritten, buffer);
+        }
+    }
+}